@@ -35,7 +35,7 @@ fn main() {
 
     // ------------------- generator polynomials coefficients LUT generation -------------------
     // NOTE: all coefficients are represented in α-notation
-    src.push_str("static GEN_COEFF:[&'static[u8];256]=[&[],&[0,0],");
+    src.push_str("static GEN_COEFF:[&[u8];256]=[&[],&[0,0],");
     let mut last = vec![0, 0_u8];
     for n in 2..=255 {
         let mut this = vec![0_u8; n as usize + 1];
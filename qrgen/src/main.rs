@@ -11,6 +11,12 @@ struct Args {
     mode: Option<_Mode>,
     #[arg(short, long)]
     version: Option<u8>,
+    #[arg(long)]
+    mask: Option<usize>,
+    #[arg(long)]
+    eci: bool,
+    #[arg(long)]
+    boost_ec: bool,
 
     #[command(subcommand)]
     output: OutputMode,
@@ -18,7 +24,10 @@ struct Args {
 
 #[derive(Subcommand, Clone)]
 enum OutputMode {
-    Print,
+    Print {
+        #[arg(long, short, default_value = "ansi")]
+        style: _Style,
+    },
     Gif {
         #[arg(long, short, default_value_t = 1)]
         upscale: usize,
@@ -28,6 +37,24 @@ enum OutputMode {
         black_color: String,
         path: String,
     },
+    Svg {
+        #[arg(long, short, default_value_t = 8)]
+        upscale: usize,
+        #[arg(long, short, default_value = "ffffff")]
+        white_color: String,
+        #[arg(long, short, default_value = "000000")]
+        black_color: String,
+        path: String,
+    },
+    Png {
+        #[arg(long, short, default_value_t = 8)]
+        upscale: usize,
+        #[arg(long, short, default_value = "ffffff")]
+        white_color: String,
+        #[arg(long, short, default_value = "000000")]
+        black_color: String,
+        path: String,
+    },
 }
 
 #[derive(ValueEnum, Clone)]
@@ -35,7 +62,15 @@ pub enum _Mode {
     Numeric,
     Alphanumeric,
     Bytes,
-    // Kanji,
+    Kanji,
+}
+
+#[derive(ValueEnum, Clone)]
+pub enum _Style {
+    Ansi,
+    HalfBlock,
+    Plain,
+    Inverted,
 }
 
 #[derive(ValueEnum, Clone)]
@@ -49,6 +84,7 @@ impl From<_Mode> for Mode {
             _Mode::Numeric => Self::Numeric,
             _Mode::Alphanumeric => Self::Alphanumeric,
             _Mode::Bytes => Self::Bytes,
+            _Mode::Kanji => Self::Kanji,
         }
     }
 }
@@ -68,53 +104,95 @@ fn main() {
     let args = Args::parse();
 
     let ec = args.ec.into();
-    let mode = args.mode.map_or_else(
-        || Mode::best_mode(args.string.as_bytes()),
-        |m| m.into(),
-    );
-    let version = args.version.map_or_else(
-        || Version::smallest_version(args.string.len(), ec, mode).unwrap(),
-        |m| Version::new(m),
-    );
-
-    let mat = QrMatrix::generate(args.string.as_bytes(), mode, version, ec);
-
-    match args.output {
-        OutputMode::Print => print!("{mat}"),
-        OutputMode::Gif { upscale, white_color, black_color, path } => {
-            use gif::*;
-
-            let size = ((mat.size() + 8) * upscale).try_into().unwrap();
-
-            let mut m = vec![0; size as usize * size as usize];
-            for y in 0..mat.size() {
-                for x in 0..mat.size() {
-                    if mat.get(x, y) {
-                        for sy in 0..upscale {
-                            for sx in 0..upscale {
-                                m[((y + 4) * upscale + sy) * size as usize + (x + 4) * upscale + sx] = 1;
-                            }
-                        }
-                    }
-                }
-            }
-
-            let black = u32::from_str_radix(&black_color, 16).expect("failed to parse color");
-            let white = u32::from_str_radix(&white_color, 16).expect("failed to parse color");
-
-            let palette = [
-                (white >> 16) as u8, (white >> 8) as u8, white as u8,
-                (black >> 16) as u8, (black >> 8) as u8, black as u8,
-            ];
-
-            let mut image = std::fs::File::create(path).unwrap();
-            let mut encoder = Encoder::new(&mut image, size, size, &palette).unwrap();
-
-            let mut frame = Frame::default();
-            frame.width = size;
-            frame.height = size;
-            frame.buffer = Cow::Owned(m);
-            encoder.write_frame(&frame).unwrap();
-        },
+
+    // kanji mode works on shift jis, so transcode when every non-ascii character can use it
+    let (sjis, _, unmappable) = encoding_rs::SHIFT_JIS.encode(&args.string);
+    let sjis_kanji = !unmappable && args.string.chars().all(|c| {
+        c.is_ascii() || Mode::best_mode_sjis(&encoding_rs::SHIFT_JIS.encode(c.encode_utf8(&mut [0; 4])).0) == Mode::Kanji
+    });
+
+    // anything else goes out as utf-8, which the builder keeps out of kanji mode
+    let (string, shift_jis) = match args.mode {
+        Some(_Mode::Kanji) => (&*sjis, true),
+        None if sjis_kanji => (&*sjis, true),
+        _ => (args.string.as_bytes(), false),
+    };
+
+    let builder = QrBuilder::new(ec).auto_utf8_eci(args.eci).boost_ec(args.boost_ec).shift_jis(shift_jis);
+    let builder = match args.version {
+        Some(v) => builder.version(or_exit(Version::new(v))),
+        None => builder,
+    };
+    let builder = match args.mask {
+        Some(m) => builder.mask(m),
+        None => builder,
+    };
+
+    // too much data for one symbol gets split into a structured append series
+    let mats = match args.mode {
+        Some(mode) => vec![or_exit(builder.segment(Segment::new(mode.into(), string)).build())],
+        None => or_exit(builder.build_structured(string)),
+    };
+
+    for (i, mat) in mats.iter().enumerate() {
+        let numbered = |path: &String| match path.rsplit_once('.') {
+            Some((name, ext)) if mats.len() > 1 => format!("{name}-{}.{ext}", i + 1),
+            None if mats.len() > 1 => format!("{path}-{}", i + 1),
+            _ => path.clone(),
+        };
+
+        match &args.output {
+            OutputMode::Print { style } => match style {
+                _Style::Ansi => print!("{mat}"),
+                _Style::HalfBlock => print!("{}", mat.render(&HalfBlock::new())),
+                _Style::Plain => print!("{}", mat.render(&HalfBlock::new().plain(true))),
+                _Style::Inverted => print!("{}", mat.render(&HalfBlock::new().plain(true).inverted(true))),
+            },
+            OutputMode::Gif { upscale, white_color, black_color, path } => {
+                write_gif(mat, *upscale, white_color, black_color, &numbered(path));
+            },
+            OutputMode::Svg { upscale, white_color, black_color, path } => {
+                let svg = Svg::new().module_size(*upscale).colors(&format!("#{black_color}"), &format!("#{white_color}"));
+                std::fs::write(numbered(path), mat.render(&svg)).unwrap();
+            },
+            OutputMode::Png { upscale, white_color, black_color, path } => {
+                let png = Png::new().module_size(*upscale).colors(parse_color(black_color), parse_color(white_color));
+                std::fs::write(numbered(path), mat.render(&png)).unwrap();
+            },
+        }
     }
 }
+
+fn or_exit<T>(result: Result<T, smolqr::Error>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    })
+}
+
+fn parse_color(color: &str) -> [u8; 3] {
+    let c = u32::from_str_radix(color, 16).expect("failed to parse color");
+    [(c >> 16) as u8, (c >> 8) as u8, c as u8]
+}
+
+fn write_gif(mat: &QrMatrix, upscale: usize, white_color: &str, black_color: &str, path: &str) {
+    use gif::*;
+
+    // palette indices, 0 white and 1 black
+    let renderer = Luma::new().module_size(upscale).colors(1, 0);
+    let width = ((mat.width() + 8) * upscale.max(1)).try_into().unwrap();
+    let height = ((mat.height() + 8) * upscale.max(1)).try_into().unwrap();
+
+    let palette = [parse_color(white_color), parse_color(black_color)].concat();
+
+    let mut image = std::fs::File::create(path).unwrap();
+    let mut encoder = Encoder::new(&mut image, width, height, &palette).unwrap();
+
+    let frame = Frame {
+        width,
+        height,
+        buffer: Cow::Owned(mat.render(&renderer)),
+        ..Frame::default()
+    };
+    encoder.write_frame(&frame).unwrap();
+}
@@ -0,0 +1,89 @@
+use alloc::{vec, vec::Vec};
+
+use crate::builder::Part;
+use crate::matrix::{apply_best_mask, function_patterns};
+use crate::penalty::scratch_len;
+use crate::structure::interleave_into;
+use crate::{encode, Error, ErrorCorrectLv, Mode, QrMatrix, Segment, Version};
+
+// for runs of symbols that share a version and level, everything generate would redo for each one
+// is worked out once: the function patterns, where the data modules go, and the scratch buffers
+#[derive(Debug, Clone)]
+pub struct QrBatch {
+    version: Version,
+    ec: ErrorCorrectLv,
+    template: QrMatrix,
+    functions: QrMatrix,
+    // data modules in placement order, the remainder bits are left out since they stay light
+    positions: Vec<(u8, u8)>,
+    data: Vec<u8>,
+    codewords: Vec<u8>,
+    scratch: Vec<u8>,
+}
+
+impl QrBatch {
+    pub fn new(version: Version, ec: ErrorCorrectLv) -> Self {
+        let codewords = version.blocks_data(ec).codewords();
+        let patterns = function_patterns(version);
+        let scratch = vec![0; scratch_len(patterns.matrix.size())];
+
+        Self {
+            version,
+            ec,
+            template: patterns.matrix,
+            functions: patterns.done,
+            positions: version.data_modules().take(codewords * 8).map(|(x, y)| (x as u8, y as u8)).collect(),
+            data: vec![0; version.max_data_bytes(ec)],
+            codewords: vec![0; codewords],
+            scratch,
+        }
+    }
+
+    pub fn version(&self) -> Version { self.version }
+
+    pub fn ec(&self) -> ErrorCorrectLv { self.ec }
+
+    pub fn generate(&mut self, string: &[u8], mode: Mode) -> Result<QrMatrix, Error> {
+        self.generate_segments(&[Segment::new(mode, string)])
+    }
+
+    // same output as QrMatrix::generate_segments at the batch's version and level
+    pub fn generate_segments(&mut self, segments: &[Segment]) -> Result<QrMatrix, Error> {
+        encode::encode_into(segments.iter().map(|s| Part::Segment(*s)), self.version, self.ec, &mut self.data)?;
+        interleave_into(&self.data, &self.version.blocks_data(self.ec), &mut self.codewords);
+
+        let mut mat = self.template.clone();
+        for (i, (x, y)) in self.positions.iter().enumerate() {
+            if self.codewords[i / 8] & (0x80 >> (i % 8)) != 0 {
+                mat.set(*x as usize, *y as usize, true);
+            }
+        }
+
+        let (mask, penalty) = apply_best_mask(&mut mat, &self.functions, self.version, self.ec, 0..8, &mut self.scratch);
+        mat.mask = Some(mask);
+        mat.penalty = Some(penalty);
+        Ok(mat)
+    }
+}
+
+#[test]
+fn test_batch() {
+    for (version, ec) in [(1, ErrorCorrectLv::H), (7, ErrorCorrectLv::L), (26, ErrorCorrectLv::Q), (40, ErrorCorrectLv::M)] {
+        let version = Version::new(version).unwrap();
+        let mut batch = QrBatch::new(version, ec);
+
+        for i in 0..5 {
+            let string = alloc::format!("LABEL-{:06}", i * 7919);
+            assert_eq!(batch.generate(string.as_bytes(), Mode::Alphanumeric), QrMatrix::generate(string.as_bytes(), Mode::Alphanumeric, version, ec));
+        }
+
+        let segments = [Segment::new(Mode::Bytes, b"lot "), Segment::new(Mode::Numeric, b"0042")];
+        assert_eq!(batch.generate_segments(&segments), QrMatrix::generate_segments(&segments, version, ec));
+    }
+
+    // a failed symbol doesn't leave anything behind for the next one
+    let mut batch = QrBatch::new(Version::MIN, ErrorCorrectLv::H);
+    assert_eq!(batch.generate(&[b'a'; 10], Mode::Bytes), Err(Error::DataTooLong { needed: 4 + 8 + 80, capacity: 72 }));
+    assert_eq!(batch.generate(b"hello", Mode::Alphanumeric), Err(Error::InvalidCharacterForMode { index: 0, byte: b'h' }));
+    assert_eq!(batch.generate(b"12345", Mode::Numeric), QrMatrix::generate(b"12345", Mode::Numeric, Version::MIN, ErrorCorrectLv::H));
+}
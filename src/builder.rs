@@ -0,0 +1,400 @@
+use alloc::{borrow::Cow, vec, vec::Vec};
+
+use crate::{encode, Error, ErrorCorrectLv, Mode, QrMatrix, Segment, Version};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Part<'a> {
+    Segment(Segment<'a>),
+    Eci(u32),
+    Fnc1First,
+    Fnc1Second(u8),
+    StructuredAppend { index: u8, total: u8, parity: u8 },
+}
+
+impl Part<'_> {
+    pub(crate) fn bit_length(&self, version: Version) -> usize {
+        match self {
+            Self::Segment(s) => s.bit_length(version),
+            Self::Eci(0..=127) => 4 + 8,
+            Self::Eci(128..=16383) => 4 + 16,
+            Self::Eci(_) => 4 + 24,
+            Self::Fnc1First => 4,
+            Self::Fnc1Second(_) => 4 + 8,
+            Self::StructuredAppend { .. } => 4 + 4 + 4 + 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrBuilder<'a> {
+    parts: Vec<Part<'a>>,
+    version: Option<Version>,
+    ec: ErrorCorrectLv,
+    utf8_eci: bool,
+    mask: Option<usize>,
+    boost_ec: bool,
+    shift_jis: bool,
+}
+
+impl<'a> QrBuilder<'a> {
+    pub fn new(ec: ErrorCorrectLv) -> Self {
+        Self {
+            parts: Vec::new(),
+            version: None,
+            ec,
+            utf8_eci: false,
+            mask: None,
+            boost_ec: false,
+            shift_jis: false,
+        }
+    }
+
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    // skips the mask selection, for reproducing a symbol exactly
+    pub fn mask(mut self, mask: usize) -> Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    // raises the level as far as the data still fits without a bigger version, the level given is the least
+    pub fn boost_ec(mut self, enabled: bool) -> Self {
+        self.boost_ec = enabled;
+        self
+    }
+
+    // says the string given to build_structured is shift jis, which lets it use kanji mode. otherwise
+    // it's taken as utf-8 or plain bytes, and never split into kanji since utf-8 can look like it
+    pub fn shift_jis(mut self, enabled: bool) -> Self {
+        self.shift_jis = enabled;
+        self
+    }
+
+    pub fn auto_utf8_eci(mut self, enabled: bool) -> Self {
+        self.utf8_eci = enabled;
+        self
+    }
+
+    pub fn segment(mut self, segment: Segment<'a>) -> Self {
+        self.parts.push(Part::Segment(segment));
+        self
+    }
+
+    pub fn segments(mut self, segments: &[Segment<'a>]) -> Self {
+        self.parts.extend(segments.iter().map(|s| Part::Segment(*s)));
+        self
+    }
+
+    pub fn eci(mut self, designator: u32) -> Self {
+        self.parts.push(Part::Eci(designator));
+        self
+    }
+
+    pub fn fnc1_first(mut self) -> Self {
+        self.parts.push(Part::Fnc1First);
+        self
+    }
+
+    pub fn fnc1_second(mut self, application: u8) -> Self {
+        self.parts.push(Part::Fnc1Second(application));
+        self
+    }
+
+    pub fn structured_append(mut self, index: u8, total: u8, parity: u8) -> Self {
+        self.parts.push(Part::StructuredAppend { index, total, parity });
+        self
+    }
+
+    pub fn bit_length(&self, version: Version) -> usize {
+        self.parts().iter().map(|p| p.bit_length(version)).sum()
+    }
+
+    pub fn smallest_version(&self) -> Result<Version, Error> {
+        (0..40).map(Version).find(|v| self.fits(*v)).ok_or_else(|| self.too_long(Version::MAX))
+    }
+
+    pub(crate) fn fits(&self, version: Version) -> bool {
+        self.parts.iter().all(|p| match p {
+            Part::Segment(s) => s.char_count() < 1 << version.char_count_length(s.mode()),
+            _ => true,
+        }) && self.bit_length(version) <= version.max_data_bytes(self.ec) * 8
+    }
+
+    fn too_long(&self, version: Version) -> Error {
+        Error::DataTooLong {
+            needed: self.bit_length(version),
+            capacity: version.max_data_bytes(self.ec) * 8,
+        }
+    }
+
+    pub fn build(&self) -> Result<QrMatrix, Error> {
+        let version = match self.version {
+            Some(v) => v,
+            None => self.smallest_version()?,
+        };
+
+        if let Some(m) = self.mask.filter(|m| *m > 7) { return Err(Error::MaskOutOfRange(m)) };
+
+        let ec = if self.boost_ec { self.boosted_ec(version) } else { self.ec };
+        Ok(QrMatrix::from_data(&encode::encode_parts(&self.parts(), version, ec)?, version, ec, self.mask))
+    }
+
+    // the char count widths only depend on the version, so the capacity is all that changes with the level
+    fn boosted_ec(&self, version: Version) -> ErrorCorrectLv {
+        let needed = self.bit_length(version);
+        [ErrorCorrectLv::H, ErrorCorrectLv::Q, ErrorCorrectLv::M].into_iter()
+            .find(|ec| *ec > self.ec && needed <= version.max_data_bytes(*ec) * 8)
+            .unwrap_or(self.ec)
+    }
+
+    pub fn build_structured(&self, string: &'a [u8]) -> Result<Vec<QrMatrix>, Error> {
+        let header = |index, total| Part::StructuredAppend { index, total, parity: parity(string) };
+
+        if let Ok(version) = self.symbol_version(None, string) {
+            return Ok(vec![self.symbol(None, string, version).build()?]);
+        }
+
+        for total in 2..=16 {
+            let chunks = split(string, total as usize, self.shift_jis);
+            let versions = chunks.iter()
+                .enumerate()
+                .map(|(i, c)| self.symbol_version(Some(header(i as u8, total)), c))
+                .collect::<Result<Vec<_>, _>>();
+
+            // keep every symbol in the series the same size
+            let Some(version) = versions.ok().and_then(|v| v.into_iter().max()) else { continue };
+
+            let symbols = chunks.iter()
+                .enumerate()
+                .map(|(i, c)| self.symbol(Some(header(i as u8, total)), c, version).build())
+                .collect();
+
+            // a chunk can still overflow at the shared version, so try again with one more symbol
+            match symbols {
+                Err(Error::DataTooLong { .. }) => continue,
+                symbols => return symbols,
+            }
+        }
+
+        let version = self.version.unwrap_or(Version::MAX);
+        Err(Error::DataTooLong {
+            needed: self.symbol(None, string, version).bit_length(version),
+            capacity: (version.max_data_bytes(self.ec) * 8 - header(0, 16).bit_length(version)) * 16,
+        })
+    }
+
+    // only the eci and fnc1 headers carry over into each symbol, segments added before would repeat in all of them
+    fn symbol(&self, header: Option<Part<'a>>, string: &'a [u8], version: Version) -> Self {
+        let mut symbol = self.clone().version(version);
+        symbol.parts = header.into_iter()
+            .chain(self.parts.iter().copied().filter(|p| matches!(p, Part::Eci(_) | Part::Fnc1First | Part::Fnc1Second(_))))
+            .chain(match self.shift_jis {
+                true => Segment::optimize_sjis(string, version),
+                false => Segment::optimize(string, version),
+            }.into_iter().map(Part::Segment))
+            .collect();
+        symbol
+    }
+
+    pub(crate) fn symbol_version(&self, header: Option<Part<'a>>, string: &'a [u8]) -> Result<Version, Error> {
+        match self.version {
+            Some(v) => {
+                let symbol = self.symbol(header, string, v);
+                if symbol.fits(v) { Ok(v) } else { Err(symbol.too_long(v)) }
+            },
+            // the char count widths only change at these versions, so one segmentation covers each range
+            None => [(0, 8), (9, 25), (26, 39)].into_iter()
+                .find_map(|(first, last)| {
+                    let symbol = self.symbol(header, string, Version(first));
+                    (first..=last).map(Version).find(|v| symbol.fits(*v))
+                })
+                .ok_or_else(|| self.symbol(header, string, Version::MAX).too_long(Version::MAX)),
+        }
+    }
+
+    fn parts(&self) -> Cow<'_, [Part<'a>]> {
+        // scanners assume iso 8859-1 for byte mode, so tell them when the bytes are really utf-8
+        let needs_eci = self.utf8_eci
+            && !self.shift_jis
+            && !self.parts.iter().any(|p| matches!(p, Part::Eci(_)))
+            && self.parts.iter().any(|p| matches!(p, Part::Segment(s) if s.mode() == Mode::Bytes && !s.data().is_ascii()));
+
+        if needs_eci {
+            // the structured append header has to stay in front
+            let at = self.parts.iter().take_while(|p| matches!(p, Part::StructuredAppend { .. })).count();
+
+            let mut parts = self.parts.clone();
+            parts.insert(at, Part::Eci(UTF8_ECI));
+            Cow::Owned(parts)
+        } else {
+            Cow::Borrowed(&self.parts)
+        }
+    }
+}
+
+pub const UTF8_ECI: u32 = 26;
+
+pub fn parity(data: &[u8]) -> u8 {
+    data.iter().fold(0, |a, b| a ^ b)
+}
+
+fn split(string: &[u8], n: usize, sjis: bool) -> Vec<&[u8]> {
+    let utf8 = core::str::from_utf8(string).ok().filter(|_| !sjis);
+    let mut chunks = Vec::with_capacity(n);
+    let mut start = 0;
+
+    for i in 1..=n {
+        let mut end = string.len() * i / n;
+
+        // don't cut a character in half if the input is text
+        if sjis && end < string.len() {
+            // shift jis can only be walked forwards, a lead byte takes its trail byte with it
+            let mut at = start;
+            while at < end {
+                at += if matches!(string[at], 0x81..=0x9f | 0xe0..=0xef) { 2 } else { 1 };
+            }
+            if at > end { end -= 1 };
+        } else if let Some(s) = utf8 {
+            while !s.is_char_boundary(end) { end -= 1 };
+        }
+
+        chunks.push(&string[start..end.max(start)]);
+        start = end.max(start);
+    }
+
+    chunks
+}
+
+#[test]
+fn test_builder() {
+    let segments = [
+        Segment::new(Mode::Alphanumeric, b"ABC"),
+        Segment::new(Mode::Numeric, b"12345"),
+    ];
+
+    assert_eq!(
+        QrBuilder::new(ErrorCorrectLv::H).segments(&segments).build(),
+        QrMatrix::generate_segments(&segments, Version::new(1).unwrap(), ErrorCorrectLv::H),
+    );
+
+    let builder = QrBuilder::new(ErrorCorrectLv::H).eci(26).segments(&segments);
+    assert_eq!(builder.bit_length(Version::new(1).unwrap()), 12 + 65 - 4);
+    assert_eq!(builder.smallest_version(), Version::new(2));
+    assert_eq!(
+        builder.clone().version(Version::new(1).unwrap()).build(),
+        Err(Error::DataTooLong { needed: 12 + 65 - 4, capacity: 72 }),
+    );
+    assert_eq!(builder.build().map(|m| m.size()), Ok(25));
+    assert_eq!(QrBuilder::new(ErrorCorrectLv::H).eci(1000000).build(), Err(Error::InvalidEci(1000000)));
+}
+
+#[test]
+fn test_auto_utf8_eci() {
+    let ascii = QrBuilder::new(ErrorCorrectLv::L).auto_utf8_eci(true).segment(Segment::new(Mode::Bytes, b"cafe"));
+    let utf8 = QrBuilder::new(ErrorCorrectLv::L).auto_utf8_eci(true).segment(Segment::new(Mode::Bytes, "café".as_bytes()));
+
+    assert_eq!(&*ascii.parts(), &[Part::Segment(Segment::new(Mode::Bytes, b"cafe"))]);
+    assert_eq!(&*utf8.parts(), &[Part::Eci(UTF8_ECI), Part::Segment(Segment::new(Mode::Bytes, "café".as_bytes()))]);
+    assert_eq!(utf8.bit_length(Version::new(1).unwrap()), 12 + 4 + 8 + 5 * 8);
+
+    let explicit = QrBuilder::new(ErrorCorrectLv::L).auto_utf8_eci(true).eci(UTF8_ECI).segment(Segment::new(Mode::Bytes, "café".as_bytes()));
+    assert_eq!(explicit.parts(), utf8.parts());
+}
+
+#[test]
+fn test_japanese() {
+    use crate::DecodedPart;
+
+    let string = "品番あいうえお-0042".as_bytes();
+
+    // utf-8 stays in byte mode behind the eci, even where it looks like shift jis kanji
+    let mats = QrBuilder::new(ErrorCorrectLv::M).auto_utf8_eci(true).build_structured(string).unwrap();
+    let decoded = mats[0].decode().unwrap();
+    assert_eq!(decoded.parts()[0], DecodedPart::Eci(UTF8_ECI));
+    assert!(decoded.parts().iter().all(|p| !matches!(p, DecodedPart::Segment { mode: Mode::Kanji, .. })));
+    assert_eq!(decoded.data(), string);
+
+    // shift jis is only split into kanji when the builder is told, and then it isn't labelled utf-8
+    let sjis = [0x95, 0x69, 0x94, 0xd4, b'-', b'0', b'0', b'4', b'2'];
+    let decoded = QrBuilder::new(ErrorCorrectLv::M).auto_utf8_eci(true).shift_jis(true).build_structured(&sjis).unwrap()[0].decode().unwrap();
+    assert!(matches!(&decoded.parts()[0], DecodedPart::Segment { mode: Mode::Kanji, data } if data[..] == sjis[..4]));
+    assert_eq!(decoded.data(), sjis);
+
+    // a series never cuts a kanji in half, which would leave both halves in byte mode
+    let sjis = [0x88, 0x9f].repeat(299);
+    let symbols = QrBuilder::new(ErrorCorrectLv::M).version(Version::new(5).unwrap()).shift_jis(true).build_structured(&sjis).unwrap();
+    assert!(symbols.len() >= 3);
+    let decoded = symbols.iter().map(|s| s.decode().unwrap()).collect::<Vec<_>>();
+    assert!(decoded.iter().flat_map(|d| d.parts()).all(|p| matches!(p, DecodedPart::StructuredAppend { .. } | DecodedPart::Segment { mode: Mode::Kanji, .. })));
+    assert_eq!(decoded.iter().flat_map(|d| d.data()).collect::<Vec<_>>(), sjis);
+}
+
+#[test]
+fn test_structured_append() {
+    let string = "0123456789".repeat(800);
+    let symbols = QrBuilder::new(ErrorCorrectLv::L).build_structured(string.as_bytes()).unwrap();
+
+    assert_eq!(symbols.len(), 2);
+    assert!(symbols.iter().all(|s| s.size() == symbols[0].size()));
+
+    assert_eq!(QrBuilder::new(ErrorCorrectLv::L).build_structured(b"HELLO").map(|s| s.len()), Ok(1));
+    assert_eq!(
+        QrBuilder::new(ErrorCorrectLv::H).build_structured(&[0; 1273 * 17]),
+        Err(Error::DataTooLong { needed: 4 + 16 + 1273 * 17 * 8, capacity: (1276 * 8 - 20) * 16 }),
+    );
+
+    // the headers go in every symbol, a segment added beforehand doesn't
+    let symbols = QrBuilder::new(ErrorCorrectLv::L)
+        .eci(UTF8_ECI)
+        .segment(Segment::new(Mode::Bytes, b"extra"))
+        .build_structured(string.as_bytes())
+        .unwrap();
+    let decoded = symbols.iter().map(|s| s.decode().unwrap()).collect::<Vec<_>>();
+    assert!(decoded.iter().all(|d| d.parts().contains(&crate::DecodedPart::Eci(UTF8_ECI))));
+    assert_eq!(decoded.iter().flat_map(|d| d.data()).collect::<Vec<_>>(), string.as_bytes());
+
+    assert_eq!(parity(b"HELLO"), b'H' ^ b'E' ^ b'L' ^ b'L' ^ b'O');
+    assert_eq!(split("aébc".as_bytes(), 2, false), &["a".as_bytes(), "ébc".as_bytes()]);
+}
+
+#[test]
+fn test_mask() {
+    let builder = QrBuilder::new(ErrorCorrectLv::M).segment(Segment::new(Mode::Bytes, b"https://example.com/"));
+    let best = builder.build().unwrap();
+
+    for m in 0..8 {
+        let mat = builder.clone().mask(m).build().unwrap();
+        assert_eq!(mat.mask(), Some(m));
+        assert!(mat.penalty().unwrap().total() >= best.penalty().unwrap().total());
+        assert_eq!(mat.decode().map(|d| (d.mask(), d.data())), Ok((m, b"https://example.com/".to_vec())));
+
+        if Some(m) == best.mask() {
+            assert_eq!(mat, best);
+        }
+    }
+
+    assert_eq!(builder.mask(8).build(), Err(Error::MaskOutOfRange(8)));
+}
+
+#[test]
+fn test_boost_ec() {
+    let v1 = Version::new(1).unwrap();
+    let boosted = |string: &[u8], ec| QrBuilder::new(ec).boost_ec(true).segment(Segment::new(Mode::Numeric, string)).build().unwrap();
+
+    // 1-H holds 17 digits, 1-Q 27, 1-M 34 and 1-L 41
+    for (len, ec) in [(17, ErrorCorrectLv::H), (18, ErrorCorrectLv::Q), (27, ErrorCorrectLv::Q), (30, ErrorCorrectLv::M), (41, ErrorCorrectLv::L)] {
+        let string = [b'7'; 41];
+        let mat = boosted(&string[..len], ErrorCorrectLv::L);
+        assert_eq!(mat.size(), 21);
+        assert_eq!(mat, QrMatrix::generate(&string[..len], Mode::Numeric, v1, ec).unwrap(), "{len} digits");
+        assert_eq!(mat.decode().map(|d| d.data()), Ok(string[..len].to_vec()));
+    }
+
+    // never lowered, and a forced version is boosted within that version
+    assert_eq!(boosted(&[b'7'; 41], ErrorCorrectLv::H).size(), 29);
+    let mat = QrBuilder::new(ErrorCorrectLv::L).boost_ec(true).version(Version::new(2).unwrap()).segment(Segment::new(Mode::Numeric, &[b'7'; 41])).build();
+    assert_eq!(mat, QrMatrix::generate(&[b'7'; 41], Mode::Numeric, Version::new(2).unwrap(), ErrorCorrectLv::Q));
+}
@@ -0,0 +1,175 @@
+use crate::encode::encode_segment;
+use crate::matrix::{format_positions, function_module, masked, FORMAT_INFO, VERSION_INFO};
+use crate::penalty::calculate_penalty;
+use crate::structure::interleave_into;
+use crate::{Error, ErrorCorrectLv, Mode, QrMatrix, Segment, Version};
+
+// data and codeword counts of version 40-L, the most any symbol needs
+const MAX_DATA_BYTES: usize = 2956;
+const MAX_CODEWORDS: usize = 3706;
+
+// embeds a code in the binary, e.g. `static CODE: &QrMatrix<[u8; 242]> = qr!("https://example.com");`
+// in the smallest version that fits, and fails to compile if nothing does. the big versions are slow
+// enough to trip long_running_const_eval, which the item holding the code can allow
+#[macro_export]
+macro_rules! qr {
+    ($data:expr) => {
+        $crate::qr!($data, $crate::Mode::Bytes, $crate::ErrorCorrectLv::M)
+    };
+    ($data:expr, $mode:expr, $ec:expr) => {{
+        const DATA: &[u8] = $data.as_bytes();
+        const VERSION: $crate::Version = match $crate::Version::smallest_version($mode.char_count(DATA.len()), $ec, $mode) {
+            Ok(version) => version,
+            Err(_) => panic!("data doesn't fit in any version"),
+        };
+        const MAT: $crate::QrMatrix<[u8; <$crate::QrMatrix>::buffer_len(VERSION)]> = match $crate::QrMatrix::generate_const(DATA, $mode, VERSION, $ec) {
+            Ok(mat) => mat,
+            Err(_) => panic!("data can't be encoded in this mode"),
+        };
+        &MAT
+    }};
+}
+
+impl<const N: usize> QrMatrix<[u8; N]> {
+    // same output as generate, but evaluable at compile time, N has to be at least buffer_len
+    pub const fn generate_const(string: &[u8], mode: Mode, version: Version, ec: ErrorCorrectLv) -> Result<Self, Error> {
+        let len = QrMatrix::buffer_len(version);
+        if N < len { return Err(Error::BufferTooSmall { needed: len, actual: N }) };
+
+        let blocks_data = version.blocks_data(ec);
+        let mut data = [0; MAX_DATA_BYTES];
+        let mut codewords = [0; MAX_CODEWORDS];
+        let (data, _) = data.split_at_mut(version.max_data_bytes(ec));
+        let (codewords, _) = codewords.split_at_mut(blocks_data.codewords());
+
+        if let Err(e) = encode_segment(&Segment::new(mode, string), version, ec, data) { return Err(e) };
+        interleave_into(data, &blocks_data, codewords);
+
+        let size = version.0 as usize * 4 + 21;
+        let mut mat = Self { bitmap: [0; N], width: size, height: size, stride: size.div_ceil(8), mask: None, penalty: None };
+        let mut functions = [0; N];
+
+        let mut y = 0;
+        while y < size {
+            let mut x = 0;
+            while x < size {
+                if let Some(v) = function_module(x, y, version) {
+                    set(&mut functions, mat.stride, x, y, true);
+                    set(&mut mat.bitmap, mat.stride, x, y, v);
+                }
+                x += 1;
+            }
+            y += 1;
+        }
+
+        // remainder bits stay light
+        let mut modules = version.data_modules();
+        let mut i = 0;
+        while let Some((x, y)) = modules.next_const() {
+            if i == codewords.len() * 8 { break };
+            set(&mut mat.bitmap, mat.stride, x, y, codewords[i / 8] & (0x80 >> (i % 8)) != 0);
+            i += 1;
+        }
+
+        // ties go to the lowest mask, like apply_best_mask
+        let mut best = mat.bitmap;
+        let mut best_penalty = usize::MAX;
+        let mut m = 0;
+        while m < 8 {
+            let mut bitmap = mat.bitmap;
+            apply_mask(&mut bitmap, &functions, mat.stride, version, ec, m);
+
+            let penalty = calculate_penalty(&bitmap, mat.stride, size);
+            if best_penalty > penalty.total() {
+                best = bitmap;
+                mat.mask = Some(m);
+                mat.penalty = Some(penalty);
+                best_penalty = penalty.total();
+            }
+            m += 1;
+        }
+
+        mat.bitmap = best;
+        Ok(mat)
+    }
+}
+
+const fn apply_mask(bitmap: &mut [u8], functions: &[u8], stride: usize, version: Version, ec: ErrorCorrectLv, mask: usize) {
+    let size = version.0 as usize * 4 + 21;
+
+    let mut y = 0;
+    while y < size {
+        let mut x = 0;
+        while x < size {
+            if !get(functions, stride, x, y) && masked(mask, x, y) {
+                set(bitmap, stride, x, y, !get(bitmap, stride, x, y));
+            }
+            x += 1;
+        }
+        y += 1;
+    }
+
+    let format = FORMAT_INFO[ec as usize * 8 + mask];
+    let mut i = 0;
+    while i < 15 {
+        let [(x1, y1), (x2, y2)] = format_positions(size, i);
+        set(bitmap, stride, x1, y1, (format << i) & 0x4000 != 0);
+        set(bitmap, stride, x2, y2, (format << i) & 0x4000 != 0);
+        i += 1;
+    }
+
+    if version.version() < 7 { return };
+
+    let info = VERSION_INFO[version.0 as usize];
+    let mut i = 0;
+    while i < 18 {
+        let v = info >> i & 1 != 0;
+        set(bitmap, stride, size - 11 + i % 3, i / 3, v);
+        set(bitmap, stride, i / 3, size - 11 + i % 3, v);
+        i += 1;
+    }
+}
+
+const fn get(bitmap: &[u8], stride: usize, x: usize, y: usize) -> bool {
+    crate::matrix::module(bitmap, stride, x, y)
+}
+
+const fn set(bitmap: &mut [u8], stride: usize, x: usize, y: usize, v: bool) {
+    bitmap[x / 8 + y * stride] &= !(1 << (x % 8));
+    bitmap[x / 8 + y * stride] |= (v as u8) << (x % 8);
+}
+
+#[test]
+fn test_const() {
+    const HELLO: Result<QrMatrix<[u8; 63]>, Error> = QrMatrix::generate_const(b"HELLO WORLD", Mode::Alphanumeric, Version::MIN, ErrorCorrectLv::Q);
+    let runtime = QrMatrix::generate(b"HELLO WORLD", Mode::Alphanumeric, Version::MIN, ErrorCorrectLv::Q).unwrap();
+    assert_eq!(HELLO.unwrap().bitmap[..], runtime.bitmap[..]);
+    assert_eq!((HELLO.unwrap().mask(), HELLO.unwrap().penalty()), (runtime.mask(), runtime.penalty()));
+
+    for (string, mode, version, ec) in [
+        (&b"https://example.com/"[..], Mode::Bytes, 3, ErrorCorrectLv::L),
+        (b"0123456789012345678901234567890123456789", Mode::Numeric, 7, ErrorCorrectLv::H),
+        (&[0x93, 0x5f, 0xe4, 0xaa], Mode::Kanji, 10, ErrorCorrectLv::M),
+        (&[b'7'; 1000], Mode::Numeric, 40, ErrorCorrectLv::Q),
+    ] {
+        let version = Version::new(version).unwrap();
+        let mat = QrMatrix::<[u8; <QrMatrix>::buffer_len(Version::MAX)]>::generate_const(string, mode, version, ec).unwrap();
+        let runtime = QrMatrix::generate(string, mode, version, ec).unwrap();
+        assert_eq!(mat.bitmap[..runtime.bitmap.len()], runtime.bitmap[..]);
+        assert_eq!(mat.size(), runtime.size());
+    }
+
+    let mat = qr!("https://example.com");
+    let mat = QrMatrix { bitmap: mat.bitmap.to_vec(), ..QrMatrix::new(mat.width, mat.height) };
+    assert_eq!(mat.decode().map(|d| d.data()), Ok(b"https://example.com".to_vec()));
+    assert_eq!(qr!("01234", Mode::Numeric, ErrorCorrectLv::H).size(), 21);
+
+    assert_eq!(
+        QrMatrix::<[u8; 10]>::generate_const(b"hi", Mode::Bytes, Version::MIN, ErrorCorrectLv::L),
+        Err(Error::BufferTooSmall { needed: 63, actual: 10 })
+    );
+    assert_eq!(
+        QrMatrix::<[u8; 63]>::generate_const(b"hi", Mode::Numeric, Version::MIN, ErrorCorrectLv::L),
+        Err(Error::InvalidCharacterForMode { index: 0, byte: b'h' })
+    );
+}
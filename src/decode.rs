@@ -0,0 +1,267 @@
+use alloc::vec::Vec;
+
+use crate::encode::{alphanumeric_table, kanji_bytes};
+use crate::{err_corr, matrix, structure, Error, ErrorCorrectLv, Mode, QrMatrix, Version};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodedPart {
+    Segment { mode: Mode, data: Vec<u8> },
+    Eci(u32),
+    Fnc1First,
+    Fnc1Second(u8),
+    StructuredAppend { index: u8, total: u8, parity: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    version: Version,
+    ec: ErrorCorrectLv,
+    mask: usize,
+    corrected: usize,
+    parts: Vec<DecodedPart>,
+}
+
+impl Decoded {
+    pub fn version(&self) -> Version { self.version }
+
+    pub fn ec(&self) -> ErrorCorrectLv { self.ec }
+
+    pub fn mask(&self) -> usize { self.mask }
+
+    pub fn corrected(&self) -> usize { self.corrected }
+
+    pub fn parts(&self) -> &[DecodedPart] { &self.parts }
+
+    pub fn data(&self) -> Vec<u8> {
+        self.parts.iter()
+            .flat_map(|p| match p {
+                DecodedPart::Segment { data, .. } => data.as_slice(),
+                _ => &[],
+            })
+            .copied()
+            .collect()
+    }
+}
+
+impl QrMatrix {
+    pub fn decode(&self) -> Result<Decoded, Error> {
+        self.decode_with_erasures(&[])
+    }
+
+    // erasures are modules known to be unreadable, which costs half as much ec as a wrong guess
+    pub fn decode_with_erasures(&self, erasures: &[(usize, usize)]) -> Result<Decoded, Error> {
+        let version = matrix::read_version(self).ok_or(Error::InvalidVersionInfo)?;
+        let (ec, mask) = matrix::read_format(self).ok_or(Error::InvalidFormatInfo)?;
+        let blocks_data = version.blocks_data(ec);
+
+        let codewords = self.unmask(version, ec, mask).read_codewords(version, blocks_data.codewords());
+        let blocks = structure::deinterleave(&codewords, version, ec);
+
+        // any codeword touching an erased module comes out nonzero
+        let mut erased = QrMatrix::new(self.size(), self.size());
+        for (x, y) in erasures.iter().filter(|(x, y)| *x < self.size() && *y < self.size()) {
+            erased.set(*x, *y, true);
+        }
+        let erased = structure::deinterleave(&erased.read_codewords(version, blocks_data.codewords()), version, ec);
+
+        let mut corrected = 0;
+        let mut data = Vec::new();
+        for (mut block, erased) in blocks.into_iter().zip(erased) {
+            let erasures = erased.iter()
+                .enumerate()
+                .filter(|(_, e)| **e != 0)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+
+            corrected += err_corr::correct(&mut block, blocks_data.ec_bytes, &erasures)?;
+            data.extend_from_slice(&block[..block.len() - blocks_data.ec_bytes]);
+        }
+
+        Ok(Decoded {
+            version,
+            ec,
+            mask,
+            corrected,
+            parts: parse(&data, version)?,
+        })
+    }
+}
+
+struct BitsReader<'a> {
+    bits: &'a [u8],
+    pos: usize,
+}
+
+impl BitsReader<'_> {
+    fn remaining(&self) -> usize {
+        self.bits.len() * 8 - self.pos
+    }
+
+    fn read(&mut self, l: usize) -> Result<usize, Error> {
+        if l > self.remaining() { return Err(Error::InvalidBitstream) };
+
+        let mut u = 0;
+        for _ in 0..l {
+            u = u << 1 | (self.bits[self.pos / 8] >> (7 - self.pos % 8) & 1) as usize;
+            self.pos += 1;
+        }
+
+        Ok(u)
+    }
+}
+
+fn parse(data: &[u8], version: Version) -> Result<Vec<DecodedPart>, Error> {
+    let mut reader = BitsReader { bits: data, pos: 0 };
+    let mut parts = Vec::new();
+
+    // the terminator may be cut short when the data fills the symbol
+    while reader.remaining() >= 4 {
+        let part = match reader.read(4)? {
+            0b0000 => break,
+            0b0111 => {
+                let first = reader.read(8)?;
+                DecodedPart::Eci(match first {
+                    0x00..=0x7f => first,
+                    0x80..=0xbf => (first & 0x3f) << 8 | reader.read(8)?,
+                    0xc0..=0xdf => (first & 0x1f) << 16 | reader.read(16)?,
+                    _ => return Err(Error::InvalidBitstream),
+                } as u32)
+            },
+            0b0101 => DecodedPart::Fnc1First,
+            0b1001 => DecodedPart::Fnc1Second(reader.read(8)? as u8),
+            0b0011 => DecodedPart::StructuredAppend {
+                index: reader.read(4)? as u8,
+                total: reader.read(4)? as u8 + 1,
+                parity: reader.read(8)? as u8,
+            },
+            indicator => {
+                let mode = Mode::from_indicator(indicator).ok_or(Error::InvalidBitstream)?;
+                let count = reader.read(version.char_count_length(mode))?;
+
+                DecodedPart::Segment {
+                    mode,
+                    data: match mode {
+                        Mode::Numeric => decode_numeric(&mut reader, count),
+                        Mode::Alphanumeric => decode_alphanumeric(&mut reader, count),
+                        Mode::Bytes => (0..count).map(|_| reader.read(8).map(|b| b as u8)).collect(),
+                        Mode::Kanji => decode_kanji(&mut reader, count),
+                    }?,
+                }
+            },
+        };
+
+        parts.push(part);
+    }
+
+    Ok(parts)
+}
+
+fn decode_numeric(reader: &mut BitsReader, count: usize) -> Result<Vec<u8>, Error> {
+    let mut result = Vec::with_capacity(count);
+
+    for digits in (0..count).step_by(3).map(|i| (count - i).min(3)) {
+        let v = reader.read([0, 4, 7, 10][digits])?;
+        if v >= [1, 10, 100, 1000][digits] { return Err(Error::InvalidBitstream) };
+
+        for d in (0..digits).rev() {
+            result.push(b'0' + (v / [1, 10, 100][d] % 10) as u8);
+        }
+    }
+
+    Ok(result)
+}
+
+fn decode_alphanumeric(reader: &mut BitsReader, count: usize) -> Result<Vec<u8>, Error> {
+    let char = |v| alphanumeric_table::char(v as u8).ok_or(Error::InvalidBitstream);
+    let mut result = Vec::with_capacity(count);
+
+    for _ in 0..count / 2 {
+        let v = reader.read(11)?;
+        if v >= 45 * 45 { return Err(Error::InvalidBitstream) };
+
+        result.push(char(v / 45)?);
+        result.push(char(v % 45)?);
+    }
+
+    if count % 2 == 1 {
+        result.push(char(reader.read(6)?)?);
+    }
+
+    Ok(result)
+}
+
+fn decode_kanji(reader: &mut BitsReader, count: usize) -> Result<Vec<u8>, Error> {
+    let mut result = Vec::with_capacity(count * 2);
+
+    for _ in 0..count {
+        result.extend(kanji_bytes(reader.read(13)? as u16));
+    }
+
+    Ok(result)
+}
+
+#[test]
+fn test_decode() {
+    use alloc::vec;
+
+    use crate::{QrBuilder, Segment};
+
+    for (string, ec) in [
+        (&b"HELLO WORLD"[..], ErrorCorrectLv::Q),
+        (b"https://example.com/?q=0123456789", ErrorCorrectLv::L),
+        (&[0x93, 0x5f, 0xe4, 0xaa, b'a', b'b'], ErrorCorrectLv::H),
+        (&[b'7'; 3000], ErrorCorrectLv::M),
+    ] {
+        let mat = QrBuilder::new(ec).segments(&Segment::optimize_sjis(string, Version::MAX)).build().unwrap();
+        let decoded = mat.decode().unwrap();
+
+        assert_eq!(decoded.data(), string);
+        assert_eq!(decoded.ec(), ec);
+        assert_eq!(decoded.version().version() as usize * 4 + 17, mat.size());
+    }
+
+    let mat = QrBuilder::new(ErrorCorrectLv::M)
+        .structured_append(1, 3, 0x42)
+        .eci(123456)
+        .fnc1_second(b'a' + 100)
+        .segment(Segment::new(Mode::Numeric, b"01234"))
+        .segment(Segment::new(Mode::Kanji, &[0x93, 0x5f]))
+        .version(Version::new(7).unwrap())
+        .build()
+        .unwrap();
+
+    assert_eq!(mat.decode().map(|d| d.parts), Ok(vec![
+        DecodedPart::StructuredAppend { index: 1, total: 3, parity: 0x42 },
+        DecodedPart::Eci(123456),
+        DecodedPart::Fnc1Second(b'a' + 100),
+        DecodedPart::Segment { mode: Mode::Numeric, data: b"01234".to_vec() },
+        DecodedPart::Segment { mode: Mode::Kanji, data: vec![0x93, 0x5f] },
+    ]));
+}
+
+#[test]
+fn test_decode_damaged() {
+    let mat = QrMatrix::generate(b"HELLO WORLD", Mode::Alphanumeric, Version::new(1).unwrap(), ErrorCorrectLv::Q).unwrap();
+
+    // the bottom right corner holds the start of the data
+    let mut damaged = mat.clone();
+    for y in 13..21 {
+        for x in 17..21 {
+            damaged.set(x, y, !damaged.get(x, y));
+        }
+    }
+
+    let decoded = damaged.decode().unwrap();
+    assert_eq!(decoded.data(), b"HELLO WORLD");
+    assert_eq!(decoded.corrected(), 4);
+
+    for y in 9..21 {
+        for x in 13..21 {
+            damaged.set(x, y, false);
+        }
+    }
+    assert_eq!(damaged.decode().map(|d| d.data()), Err(Error::TooManyErrors));
+
+    let erasures = (9..21).flat_map(|y| (13..21).map(move |x| (x, y))).collect::<Vec<_>>();
+    assert_eq!(damaged.decode_with_erasures(&erasures).map(|d| d.data()), Ok(b"HELLO WORLD".to_vec()));
+}
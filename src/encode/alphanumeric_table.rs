@@ -1,4 +1,4 @@
-pub fn get(c: u8) -> Option<u8> {
+pub const fn get(c: u8) -> Option<u8> {
     match c {
         b'0' => Some(0),
         b'1' => Some(1),
@@ -48,3 +48,7 @@ pub fn get(c: u8) -> Option<u8> {
         _ => None,
     }
 }
+
+pub fn char(v: u8) -> Option<u8> {
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:".get(v as usize).copied()
+}
@@ -1,43 +1,47 @@
 pub(crate) mod alphanumeric_table;
 
+use alloc::{vec, vec::Vec};
+
 use crate::*;
+use crate::builder::Part;
 
-struct BitsWriter {
-    pub bits: Vec<u8>,
-    pub last_length: u8,
+// writes into a borrowed buffer, bits past its end are only counted so overflows can still be reported
+struct BitsWriter<'a> {
+    pub bits: &'a mut [u8],
+    len: usize,
 }
 
-impl BitsWriter {
-    pub fn new() -> Self {
-        Self { bits: vec![0], last_length: 0 }
+// const so generate_const can share it, hence the while loops
+impl<'a> BitsWriter<'a> {
+    pub const fn new(bits: &'a mut [u8]) -> Self {
+        let mut i = 0;
+        while i < bits.len() {
+            bits[i] = 0;
+            i += 1;
+        }
+        Self { bits, len: 0 }
     }
 
-    pub fn len(&self) -> usize {
-        self.bits.len() * 8 - 8 + self.last_length as usize
+    pub const fn len(&self) -> usize {
+        self.len
     }
 
-    pub fn write_bit(&mut self, b: bool) {
-        *self.bits.last_mut().unwrap() |= (b as u8) << (7 - self.last_length);
-        self.last_length += 1;
-
-        if self.last_length >= 8 {
-            self.bits.push(0);
-            self.last_length = 0;
+    pub const fn write_bit(&mut self, b: bool) {
+        if self.len / 8 < self.bits.len() {
+            self.bits[self.len / 8] |= (b as u8) << (7 - self.len % 8);
         }
+        self.len += 1;
     }
 
-    pub fn write_bits(&mut self, l: usize, u: usize) {
-        for i in 0..l {
+    pub const fn write_bits(&mut self, l: usize, u: usize) {
+        let mut i = 0;
+        while i < l {
             self.write_bit(u & (1 << (l - i - 1)) != 0);
+            i += 1;
         }
     }
 
-    pub fn write_u8_aligned(&mut self, u: u8) {
-        assert_eq!(self.last_length, 0);
-        *self.bits.last_mut().unwrap() = u;
-        self.bits.push(0);
-    }
-
+    #[cfg(feature = "std")]
     #[allow(unused)]
     pub fn dump(&self) {
         for b in self.bits.iter() {
@@ -46,46 +50,159 @@ impl BitsWriter {
         println!();
     }
 
-    pub fn align(&mut self) {
-        if self.last_length != 0 {
-            self.bits.push(0);
-            self.last_length = 0;
-        }
+    pub const fn align(&mut self) {
+        self.len = self.len.next_multiple_of(8);
     }
 }
 
-pub fn encode(string: &[u8], mode: Mode, version: Version, ec: ErrorCorrectLv) -> Option<Vec<u8>> {
-    let mut buffer = BitsWriter::new();
-    buffer.write_bits(4, mode.indicator() as usize);
-    buffer.write_bits(version.char_count_length(mode), string.len());
+pub fn encode(string: &[u8], mode: Mode, version: Version, ec: ErrorCorrectLv) -> Result<Vec<u8>, Error> {
+    encode_segments(&[Segment::new(mode, string)], version, ec)
+}
 
-    match mode {
-        Mode::Numeric => encode_numeric(string, &mut buffer),
-        Mode::Alphanumeric => encode_alphanumeric(string, &mut buffer),
-        Mode::Bytes => encode_bytes(string, &mut buffer),
-        Mode::Kanji => todo!(),
-    }?;
+pub fn encode_segments(segments: &[Segment], version: Version, ec: ErrorCorrectLv) -> Result<Vec<u8>, Error> {
+    let mut data = vec![0; version.max_data_bytes(ec)];
+    encode_into(segments.iter().map(|s| Part::Segment(*s)), version, ec, &mut data)?;
+    Ok(data)
+}
 
+pub fn encode_parts(parts: &[Part], version: Version, ec: ErrorCorrectLv) -> Result<Vec<u8>, Error> {
+    let mut data = vec![0; version.max_data_bytes(ec)];
+    encode_into(parts.iter().copied(), version, ec, &mut data)?;
+    Ok(data)
+}
+
+// data has to be exactly max_data_bytes long
+pub(crate) fn encode_into<'a>(parts: impl Iterator<Item = Part<'a>> + Clone, version: Version, ec: ErrorCorrectLv, data: &mut [u8]) -> Result<(), Error> {
+    let mut buffer = BitsWriter::new(data);
     let bytes = version.max_data_bytes(ec);
+    let too_long = || Error::DataTooLong {
+        needed: parts.clone().map(|p| p.bit_length(version)).sum(),
+        capacity: bytes * 8,
+    };
+
+    for p in parts.clone() {
+        match p {
+            Part::Segment(s) => {
+                if s.char_count() >= 1 << version.char_count_length(s.mode()) { return Err(too_long()) };
+                write_segment(&s, version, &mut buffer)?;
+            },
+            Part::Eci(designator) => {
+                let designator = designator as usize;
+
+                buffer.write_bits(4, 0b0111);
+                match designator {
+                    0..=127 => buffer.write_bits(8, designator),
+                    128..=16383 => buffer.write_bits(16, 0b10 << 14 | designator),
+                    16384..=999999 => buffer.write_bits(24, 0b110 << 21 | designator),
+                    _ => return Err(Error::InvalidEci(designator as u32)),
+                }
+            },
+            Part::Fnc1First => buffer.write_bits(4, 0b0101),
+            Part::Fnc1Second(app) => {
+                buffer.write_bits(4, 0b1001);
+                buffer.write_bits(8, app as usize);
+            },
+            Part::StructuredAppend { index, total, parity } => {
+                if !(1..=16).contains(&total) || index >= total {
+                    return Err(Error::InvalidStructuredAppend { index, total });
+                }
+
+                buffer.write_bits(4, 0b0011);
+                buffer.write_bits(4, index as usize);
+                buffer.write_bits(4, total as usize - 1);
+                buffer.write_bits(8, parity as usize);
+            },
+        }
+    }
+
+    if buffer.len() > bytes * 8 { return Err(too_long()) };
+
+    finish(buffer, bytes * 8, 4);
+    Ok(())
+}
+
+// the one segment version of encode_into, for generate_const
+pub(crate) const fn encode_segment(segment: &Segment, version: Version, ec: ErrorCorrectLv, data: &mut [u8]) -> Result<(), Error> {
+    let mut buffer = BitsWriter::new(data);
+    let capacity = version.max_data_bytes(ec) * 8;
+    let too_long = Error::DataTooLong { needed: segment.bit_length(version), capacity };
+
+    if segment.char_count() >= 1 << version.char_count_length(segment.mode()) { return Err(too_long) };
+    if let Err(e) = write_segment(segment, version, &mut buffer) { return Err(e) };
+    if buffer.len() > capacity { return Err(too_long) };
+
+    finish(buffer, capacity, 4);
+    Ok(())
+}
+
+const fn write_segment(segment: &Segment, version: Version, buffer: &mut BitsWriter) -> Result<(), Error> {
+    buffer.write_bits(4, segment.mode().indicator() as usize);
+    buffer.write_bits(version.char_count_length(segment.mode()), segment.char_count());
+    encode_data(segment, buffer)
+}
+
+pub fn encode_micro(segments: &[Segment], version: MicroVersion, ec: ErrorCorrectLv) -> Result<Vec<u8>, Error> {
+    version.fits(segments, ec)?;
+
+    let capacity = version.data_bits(ec)?;
+    let mut data = vec![0; capacity.div_ceil(8)];
+    let mut buffer = BitsWriter::new(&mut data);
+    for s in segments.iter() {
+        buffer.write_bits(version.indicator_length(), s.mode() as usize);
+        buffer.write_bits(version.char_count_length(s.mode())?, s.char_count());
+        encode_data(s, &mut buffer)?;
+    }
+
+    finish(buffer, capacity, version.terminator_length());
+    Ok(data)
+}
+
+pub fn encode_rmqr(segments: &[Segment], version: RmqrVersion, ec: ErrorCorrectLv) -> Result<Vec<u8>, Error> {
+    version.fits(segments, ec)?;
+
+    let bytes = version.max_data_bytes(ec)?;
+    let mut data = vec![0; bytes];
+    let mut buffer = BitsWriter::new(&mut data);
+    for s in segments.iter() {
+        // rmqr numbers the modes 001 to 100 in declaration order, in three bits
+        buffer.write_bits(3, s.mode() as usize + 1);
+        buffer.write_bits(version.char_count_length(s.mode()), s.char_count());
+        encode_data(s, &mut buffer)?;
+    }
 
-    buffer.write_bits((bytes * 8 - buffer.len()).min(4), 0);
+    finish(buffer, bytes * 8, 3);
+    Ok(data)
+}
+
+const fn encode_data(segment: &Segment, buffer: &mut BitsWriter) -> Result<(), Error> {
+    match segment.mode() {
+        Mode::Numeric => encode_numeric(segment.data(), buffer),
+        Mode::Alphanumeric => encode_alphanumeric(segment.data(), buffer),
+        Mode::Bytes => encode_bytes(segment.data(), buffer),
+        Mode::Kanji => encode_kanji(segment.data(), buffer),
+    }
+}
+
+// terminator, then pad codewords up to the capacity, the last one only 4 bits wide for m1 and m3
+const fn finish(mut buffer: BitsWriter, capacity: usize, terminator: usize) {
+    let room = capacity - buffer.len();
+    buffer.write_bits(if room < terminator { room } else { terminator }, 0);
     buffer.align();
 
     let mut even = true;
-    for _ in buffer.bits.len()..=bytes {
-        buffer.write_u8_aligned(if even { 0b11101100 } else { 0b00010001 });
+    let mut i = buffer.len() / 8;
+    while i < capacity / 8 {
+        buffer.bits[i] = if even { 0b11101100 } else { 0b00010001 };
         even ^= true;
+        i += 1;
     }
-
-    buffer.bits.pop();
-    Some(buffer.bits)
 }
 
 #[test]
 fn test_encode() {
     assert_eq!(
-        encode(b"HELLO WORLD", Mode::Alphanumeric, Version::new(1), ErrorCorrectLv::Q),
-        Some(vec![
+        encode(b"HELLO WORLD", Mode::Alphanumeric, Version::new(1).unwrap(), ErrorCorrectLv::Q),
+        Ok(vec![
             0b00100000, 0b01011011, 0b00001011, 0b01111000,
             0b11010001, 0b01110010, 0b11011100, 0b01001101,
             0b01000011, 0b01000000, 0b11101100, 0b00010001,
@@ -94,60 +211,197 @@ fn test_encode() {
     );
 }
 
-fn encode_numeric(string: &[u8], buffer: &mut BitsWriter) -> Option<()> {
-    for p in string.chunks(3) {
+#[test]
+fn test_encode_segments() {
+    let segments = [
+        Segment::new(Mode::Alphanumeric, b"ABC"),
+        Segment::new(Mode::Numeric, b"12345"),
+    ];
+
+    assert_eq!(
+        encode_segments(&segments, Version::new(1).unwrap(), ErrorCorrectLv::H),
+        Ok(vec![
+            0b00100000, 0b00011001, 0b11001101, 0b00110000,
+            0b01000000, 0b01010001, 0b11101101, 0b01101000,
+            0b00000000,
+        ])
+    );
+    assert_eq!(
+        encode(&[b'1'; 42], Mode::Numeric, Version::new(1).unwrap(), ErrorCorrectLv::L),
+        Err(Error::DataTooLong { needed: 154, capacity: 152 })
+    );
+}
+
+#[test]
+fn test_encode_micro() {
+    // m1 ends on a 4 bit codeword, which comes back in the high half of the last byte
+    assert_eq!(
+        encode_micro(&[Segment::new(Mode::Numeric, b"01234")], MicroVersion::new(1).unwrap(), ErrorCorrectLv::L),
+        Ok(vec![0b10100000, 0b01100010, 0b00100000]),
+    );
+    assert_eq!(
+        encode_micro(&[Segment::new(Mode::Alphanumeric, b"AB")], MicroVersion::new(2).unwrap(), ErrorCorrectLv::M),
+        Ok(vec![0b10100011, 0b10011010, 0b00000000, 0b11101100]),
+    );
+}
+
+#[test]
+fn test_encode_headers() {
+    let parts = [
+        Part::Eci(26),
+        Part::Fnc1Second(b'a' + 100),
+        Part::Eci(9999),
+        Part::Eci(123456),
+        Part::StructuredAppend { index: 2, total: 5, parity: 0x5a },
+    ];
+
+    assert_eq!(
+        encode_parts(&parts, Version::new(1).unwrap(), ErrorCorrectLv::L).map(|b| b[..13].to_vec()),
+        Ok(vec![
+            0b01110001, 0b10101001, 0b11000101, 0b01111010,
+            0b01110000, 0b11110111, 0b11000001, 0b11100010,
+            0b01000000, 0b00110010, 0b01000101, 0b10100000,
+            0b11101100,
+        ])
+    );
+    assert_eq!(encode_parts(&[Part::Eci(1000000)], Version::new(1).unwrap(), ErrorCorrectLv::L), Err(Error::InvalidEci(1000000)));
+    assert_eq!(
+        encode_parts(&[Part::StructuredAppend { index: 3, total: 3, parity: 0 }], Version::new(1).unwrap(), ErrorCorrectLv::L),
+        Err(Error::InvalidStructuredAppend { index: 3, total: 3 })
+    );
+}
+
+const fn encode_numeric(string: &[u8], buffer: &mut BitsWriter) -> Result<(), Error> {
+    let mut i = 0;
+    while i < string.len() {
         let mut parse = 0;
-        for c in p.iter() {
-            parse = parse * 10 + (*c as char).to_digit(10)? as usize;
+        let mut j = i;
+        while j < i + 3 && j < string.len() {
+            let Some(d) = (string[j] as char).to_digit(10) else {
+                return Err(Error::InvalidCharacterForMode { index: j, byte: string[j] });
+            };
+            parse = parse * 10 + d as usize;
+            j += 1;
         }
 
-        match p.len() {
+        match j - i {
             3 => buffer.write_bits(10, parse),
             2 => buffer.write_bits(7, parse),
             1 => buffer.write_bits(4, parse),
             _ => unreachable!()
         }
+        i = j;
     }
 
-    Some(())
+    Ok(())
 }
 
 #[test]
 fn test_numeric() {
-    let mut w = BitsWriter::new();
-    assert!(encode_numeric(b"8675309", &mut w).is_some());
+    let mut bits = [0; 4];
+    let mut w = BitsWriter::new(&mut bits);
+    assert!(encode_numeric(b"8675309", &mut w).is_ok());
 
-    assert_eq!(&w.bits, &[0b1101_1000, 0b1110_0001, 0b0010_1001, 0]);
-    assert_eq!(w.last_length, 0);
+    assert_eq!(w.bits, &[0b1101_1000, 0b1110_0001, 0b0010_1001, 0]);
+    assert_eq!(w.len(), 24);
+
+    assert_eq!(encode_numeric(b"8675a09", &mut w), Err(Error::InvalidCharacterForMode { index: 4, byte: b'a' }));
 }
 
-fn encode_alphanumeric(string: &[u8], buffer: &mut BitsWriter) -> Option<()> {
-    use alphanumeric_table::get;
+const fn encode_alphanumeric(string: &[u8], buffer: &mut BitsWriter) -> Result<(), Error> {
+    let mut i = 0;
+    while i < string.len() {
+        let Some(a) = alphanumeric_table::get(string[i]) else {
+            return Err(Error::InvalidCharacterForMode { index: i, byte: string[i] });
+        };
 
-    for p in string.chunks(2) {
-        if p.len() == 2 {
-            buffer.write_bits(11, get(p[0])? as usize * 45 + get(p[1])? as usize);
+        if i + 1 < string.len() {
+            let Some(b) = alphanumeric_table::get(string[i + 1]) else {
+                return Err(Error::InvalidCharacterForMode { index: i + 1, byte: string[i + 1] });
+            };
+            buffer.write_bits(11, a as usize * 45 + b as usize);
         } else {
-            buffer.write_bits(6, get(p[0])? as usize);
+            buffer.write_bits(6, a as usize);
         }
+        i += 2;
     }
 
-    Some(())
+    Ok(())
 }
 
 #[test]
 fn test_alphanumeric() {
-    let mut w = BitsWriter::new();
-    assert!(encode_alphanumeric(b"HELLO WORLD", &mut w).is_some());
+    let mut bits = [0; 8];
+    let mut w = BitsWriter::new(&mut bits);
+    assert!(encode_alphanumeric(b"HELLO WORLD", &mut w).is_ok());
+
+    assert_eq!(w.bits, &[0b0110_0001, 0b0110_1111, 0b0001_1010, 0b0010_1110, 0b0101_1011, 0b1000_1001, 0b1010_1000, 0b0110_1000]);
+    assert_eq!(w.len(), 61);
 
-    assert_eq!(&w.bits, &[0b0110_0001, 0b0110_1111, 0b0001_1010, 0b0010_1110, 0b0101_1011, 0b1000_1001, 0b1010_1000, 0b0110_1000]);
-    assert_eq!(w.last_length, 5);
+    assert_eq!(encode_alphanumeric(b"HELLO world", &mut w), Err(Error::InvalidCharacterForMode { index: 6, byte: b'w' }));
 }
 
-fn encode_bytes(string: &[u8], buffer: &mut BitsWriter) -> Option<()> {
-    for b in string.iter() {
-        buffer.write_bits(8, *b as usize);
+const fn encode_bytes(string: &[u8], buffer: &mut BitsWriter) -> Result<(), Error> {
+    let mut i = 0;
+    while i < string.len() {
+        buffer.write_bits(8, string[i] as usize);
+        i += 1;
     }
 
-    Some(())
+    Ok(())
+}
+
+const fn encode_kanji(string: &[u8], buffer: &mut BitsWriter) -> Result<(), Error> {
+    let mut i = 0;
+    while i < string.len() {
+        let invalid = Error::InvalidCharacterForMode { index: i, byte: string[i] };
+        if i + 1 >= string.len() { return Err(invalid) };
+        let Some(v) = kanji_value(string[i], string[i + 1]) else { return Err(invalid) };
+
+        buffer.write_bits(13, v as usize);
+        i += 2;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_kanji() {
+    let mut bits = [0; 4];
+    let mut w = BitsWriter::new(&mut bits);
+    assert!(encode_kanji(&[0x93, 0x5f, 0xe4, 0xaa], &mut w).is_ok());
+
+    assert_eq!(w.bits, &[0b0110_1100, 0b1111_1110, 0b1010_1010, 0b1000_0000]);
+    assert_eq!(w.len(), 26);
+
+    assert_eq!(encode_kanji(&[0x93, 0x5f, 0x41], &mut BitsWriter::new(&mut [0; 4])), Err(Error::InvalidCharacterForMode { index: 2, byte: 0x41 }));
+    assert_eq!(encode_kanji(&[0x41, 0x42], &mut BitsWriter::new(&mut [0; 4])), Err(Error::InvalidCharacterForMode { index: 0, byte: 0x41 }));
+
+    assert_eq!(Mode::best_mode_sjis(&[0x93, 0x5f, 0xe4, 0xaa]), Mode::Kanji);
+    assert_eq!(Mode::best_mode_sjis(&[0x93, 0x5f, 0xe4]), Mode::Bytes);
+    assert_eq!(Mode::best_mode(&[0x93, 0x5f, 0xe4, 0xaa]), Mode::Bytes);
+
+    // "あい" in utf-8 is made of valid shift jis kanji pairs too
+    assert_eq!(Mode::best_mode("あい".as_bytes()), Mode::Bytes);
+    assert_eq!(Mode::best_mode_sjis("あい".as_bytes()), Mode::Kanji);
+}
+
+pub(crate) const fn kanji_value(hi: u8, lo: u8) -> Option<u16> {
+    if lo < 0x40 || lo > 0xfc || lo == 0x7f { return None };
+
+    let c = u16::from_be_bytes([hi, lo]);
+    let c = match c {
+        0x8140..=0x9ffc => c - 0x8140,
+        0xe040..=0xebbf => c - 0xc140,
+        _ => return None,
+    };
+
+    Some((c >> 8) * 0xc0 + (c & 0xff))
+}
+
+pub(crate) fn kanji_bytes(v: u16) -> [u8; 2] {
+    let c = ((v / 0xc0) << 8) | (v % 0xc0);
+    let c = if c + 0x8140 <= 0x9ffc { c + 0x8140 } else { c + 0xc140 };
+
+    c.to_be_bytes()
 }
@@ -1,62 +1,225 @@
 include!(concat!(env!("OUT_DIR"), "/ec_tables.rs"));
 
-pub fn generate_ec(a: &[u8], bytes: usize) -> Vec<u8> {
-    let mut r = long_div(a, GEN_COEFF[bytes], bytes);
-    r.drain(bytes..);
-    r
-}
+use alloc::{vec, vec::Vec};
+
+use crate::Error;
 
-fn long_div(a: &[u8], b: &[u8], pad_a: usize) -> Vec<u8> {
-    let mut result = vec![0_u8; a.len() + pad_a];
-    result[pad_a..].copy_from_slice(a);
+// the most any block of any symbol needs
+pub const MAX_EC_BYTES: usize = 30;
 
-    for _i in 0..a.len() {
-        let i = result.len() - _i - 1;
+// remainder of the data (highest coefficient first) divided by the generator, written into ec
+// const so generate_const can read the tables too
+pub const fn generate_ec(data: &[u8], ec: &mut [u8]) {
+    let n = ec.len();
+    let generator = GEN_COEFF[n];
 
-        let c = LOG[result[i] as usize];
+    let mut i = 0;
+    while i < n {
+        ec[i] = 0;
+        i += 1;
+    }
 
-        for (a, b) in result.iter_mut().rev()
-            .skip(_i)
-            .zip(b.iter().rev())
-        {
-            let d = *b as usize + c as usize;
+    let mut i = 0;
+    while i < data.len() {
+        let factor = data[i] ^ ec[0];
+        let mut j = 0;
+        while j + 1 < n {
+            ec[j] = ec[j + 1];
+            j += 1;
+        }
+        ec[n - 1] = 0;
 
-            *a ^= ANTILOG[d % 256 + d / 256];
+        // the generator is little-endian and its leading coefficient is always α^0
+        if factor != 0 {
+            let c = LOG[factor as usize] as usize;
+            let mut j = 0;
+            while j < n {
+                ec[j] ^= ANTILOG[(generator[n - 1 - j] as usize + c) % 255];
+                j += 1;
+            }
         }
+        i += 1;
     }
+}
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 { return 0 };
+    ANTILOG[(LOG[a as usize] as usize + LOG[b as usize] as usize) % 255]
+}
+
+fn inv(a: u8) -> u8 {
+    ANTILOG[(255 - LOG[a as usize] as usize) % 255]
+}
+
+fn pow(e: usize) -> u8 {
+    ANTILOG[e % 255]
+}
 
+// coefficients are little-endian here, same as long_div
+fn eval(p: &[u8], x: u8) -> u8 {
+    p.iter().rev().fold(0, |acc, c| mul(acc, x) ^ c)
+}
+
+fn poly_mul(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; a.len() + b.len() - 1];
+    for (i, a) in a.iter().enumerate() {
+        for (j, b) in b.iter().enumerate() {
+            result[i + j] ^= mul(*a, *b);
+        }
+    }
     result
 }
 
+// received codeword in transmission order, so index i sits at power len - 1 - i
+fn syndromes(block: &[u8], ec_bytes: usize) -> Vec<u8> {
+    (0..ec_bytes)
+        .map(|j| block.iter().fold(0, |acc, c| mul(acc, pow(j)) ^ c))
+        .collect()
+}
+
+// fixes the block in place and returns how many bytes were wrong, erasures are indices into the block
+pub fn correct(block: &mut [u8], ec_bytes: usize, erasures: &[usize]) -> Result<usize, Error> {
+    let s = syndromes(block, ec_bytes);
+    if s.iter().all(|s| *s == 0) { return Ok(0) };
+    if erasures.len() > ec_bytes { return Err(Error::TooManyErrors) };
+
+    let len = block.len();
+    let locator = |i: usize| pow(len - 1 - i);
+
+    // berlekamp-massey, seeded with the erasure locator so only the unknown errors are searched for
+    let mut lambda = erasures.iter().fold(vec![1], |p, i| poly_mul(&p, &[1, locator(*i)]));
+    let mut b = lambda.clone();
+    let mut l = erasures.len();
+
+    for k in erasures.len()..ec_bytes {
+        let delta = lambda.iter()
+            .enumerate()
+            .take(k + 1)
+            .fold(0, |d, (j, c)| d ^ mul(*c, s[k - j]));
+
+        b.insert(0, 0);
+        if delta == 0 { continue };
+
+        let mut t = lambda.clone();
+        t.resize(t.len().max(b.len()), 0);
+        for (t, b) in t.iter_mut().zip(b.iter()) {
+            *t ^= mul(delta, *b);
+        }
+
+        if 2 * l <= k + erasures.len() {
+            l = k + 1 + erasures.len() - l;
+            b = lambda.iter().map(|c| mul(*c, inv(delta))).collect();
+        }
+
+        lambda = t;
+    }
+
+    while lambda.last() == Some(&0) { lambda.pop(); }
+    if lambda.len() - 1 != l || 2 * l - erasures.len() > ec_bytes { return Err(Error::TooManyErrors) };
+
+    // chien search, every root has to land inside the block
+    let positions = (0..block.len())
+        .filter(|i| eval(&lambda, inv(locator(*i))) == 0)
+        .collect::<Vec<_>>();
+    if positions.len() != l { return Err(Error::TooManyErrors) };
+
+    // forney, with the generator's roots starting at α^0
+    let mut omega = poly_mul(&s, &lambda);
+    omega.truncate(ec_bytes);
+    let derivative = lambda.iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, c)| if i % 2 == 1 { *c } else { 0 })
+        .collect::<Vec<_>>();
+
+    let mut corrected = 0;
+    for i in positions {
+        let x = locator(i);
+        let d = eval(&derivative, inv(x));
+        if d == 0 { return Err(Error::TooManyErrors) };
+
+        let magnitude = mul(x, mul(eval(&omega, inv(x)), inv(d)));
+        block[i] ^= magnitude;
+        corrected += (magnitude != 0) as usize;
+    }
+
+    if syndromes(block, ec_bytes).iter().any(|s| *s != 0) { return Err(Error::TooManyErrors) };
+    Ok(corrected)
+}
+
+#[test]
+fn test_correct() {
+    let data = b"some data to protect";
+    let mut ec = [0; 10];
+    generate_ec(data, &mut ec);
+
+    let mut block = data.to_vec();
+    block.extend(ec);
+    let clean = block.clone();
+
+    assert_eq!(correct(&mut block.clone(), 10, &[]), Ok(0));
+
+    // 5 errors is the most 10 ec bytes can fix
+    for i in [0, 3, 7, 19, 29] {
+        block[i] ^= 0x5a;
+    }
+    assert_eq!(correct(&mut block, 10, &[]), Ok(5));
+    assert_eq!(block, clean);
+
+    // one error costs as much as two erasures
+    for i in [1, 2, 4, 5, 6, 8, 9, 10] {
+        block[i] = 0;
+    }
+    block[20] ^= 1;
+    assert_eq!(correct(&mut block, 10, &[1, 2, 4, 5, 6, 8, 9, 10]), Ok(9));
+    assert_eq!(block, clean);
+
+    for i in [0, 3, 7, 19, 25, 29] {
+        block[i] ^= 0xff;
+    }
+    assert_eq!(correct(&mut block, 10, &[]), Err(Error::TooManyErrors));
+
+    // zero coefficients in the error evaluator
+    let mut zeros = vec![0, 0, 7, 0, 0, 0, 0, 0];
+    generate_ec(&[0, 0, 7, 0], &mut zeros[4..]);
+    let clean = zeros.clone();
+    zeros[0] ^= 5;
+    zeros[3] ^= 9;
+    assert_eq!(correct(&mut zeros, 4, &[]), Ok(2));
+    assert_eq!(zeros, clean);
+}
+
 #[test]
 fn test() {
-    assert_eq!(generate_ec(&[
-        17,
+    let mut ec = [0; 10];
+    generate_ec(&[
+        32,
+        91,
+        11,
+        120,
+        209,
+        114,
+        220,
+        77,
+        67,
+        64,
         236,
         17,
         236,
         17,
         236,
-        64,
-        67,
-        77,
-        220,
-        114,
-        209,
-        120,
-        11,
-        91,
-        32,
-    ], 10), vec![
-        23,
-        93,
-        226,
-        231,
-        215,
-        235,
-        119,
-        39,
-        35,
+        17,
+    ], &mut ec);
+    assert_eq!(ec, [
         196,
+        35,
+        39,
+        119,
+        235,
+        215,
+        231,
+        226,
+        93,
+        23,
     ]);
 }
@@ -0,0 +1,48 @@
+use crate::{ErrorCorrectLv, Mode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    // sizes are in bits
+    DataTooLong { needed: usize, capacity: usize },
+    InvalidCharacterForMode { index: usize, byte: u8 },
+    VersionOutOfRange(u8),
+    InvalidEci(u32),
+    InvalidStructuredAppend { index: u8, total: u8 },
+    InvalidFormatInfo,
+    InvalidVersionInfo,
+    InvalidBitstream,
+    TooManyErrors,
+    ImageSizeMismatch { expected: usize, actual: usize },
+    MicroVersionOutOfRange(u8),
+    UnsupportedMode(Mode),
+    UnsupportedEcLevel(ErrorCorrectLv),
+    InvalidRmqrSize { height: usize, width: usize },
+    MaskOutOfRange(usize),
+    // sizes are in bytes
+    BufferTooSmall { needed: usize, actual: usize },
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::DataTooLong { needed, capacity } => write!(f, "data needs {needed} bits but only {capacity} fit"),
+            Self::InvalidCharacterForMode { index, byte } => write!(f, "byte {byte:#04x} at {index} can't be encoded in this mode"),
+            Self::VersionOutOfRange(v) => write!(f, "version {v} is not in 1..=40"),
+            Self::InvalidEci(d) => write!(f, "eci designator {d} is not in 0..=999999"),
+            Self::InvalidStructuredAppend { index, total } => write!(f, "structured append symbol {index} of {total} is invalid"),
+            Self::InvalidFormatInfo => write!(f, "format info is unreadable"),
+            Self::InvalidVersionInfo => write!(f, "version info is unreadable"),
+            Self::InvalidBitstream => write!(f, "data bitstream is malformed"),
+            Self::TooManyErrors => write!(f, "too many errors to correct"),
+            Self::ImageSizeMismatch { expected, actual } => write!(f, "image needs {expected} pixels but got {actual}"),
+            Self::MicroVersionOutOfRange(v) => write!(f, "micro version {v} is not in 1..=4"),
+            Self::UnsupportedMode(mode) => write!(f, "{mode:?} mode is not available in this symbol"),
+            Self::UnsupportedEcLevel(ec) => write!(f, "error correction level {ec:?} is not available in this symbol"),
+            Self::InvalidRmqrSize { height, width } => write!(f, "there is no {height}x{width} rmqr symbol"),
+            Self::MaskOutOfRange(m) => write!(f, "mask {m} is not in 0..=7"),
+            Self::BufferTooSmall { needed, actual } => write!(f, "buffer needs {needed} bytes but only has {actual}"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
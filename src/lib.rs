@@ -1,12 +1,36 @@
-#![feature(array_chunks)]
-#![feature(iter_array_chunks)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
+mod batch;
+mod builder;
+mod const_gen;
+mod decode;
 mod encode;
 mod err_corr;
+mod error;
 mod matrix;
+mod micro;
+mod penalty;
+mod render;
+mod rmqr;
+#[cfg(feature = "std")]
+mod scan;
+mod segment;
 mod structure;
 
-pub use matrix::QrMatrix;
+pub use batch::QrBatch;
+pub use builder::{QrBuilder, UTF8_ECI};
+pub use decode::{Decoded, DecodedPart};
+pub use error::Error;
+pub use matrix::{DataModules, QrMatrix};
+pub use micro::MicroVersion;
+pub use penalty::{Penalty, PenaltyReport};
+pub use render::{Ansi, Canvas, HalfBlock, Luma, Pbm, Pgm, Png, Renderer, Rgba, Svg};
+pub use rmqr::RmqrVersion;
+#[cfg(feature = "std")]
+pub use scan::{scan, GrayImage};
+pub use segment::Segment;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
@@ -17,7 +41,7 @@ pub enum Mode {
 }
 
 impl Mode {
-    fn indicator(self) -> u8 {
+    const fn indicator(self) -> u8 {
         match self {
             Self::Numeric => 0b0001,
             Self::Alphanumeric => 0b0010,
@@ -26,7 +50,27 @@ impl Mode {
         }
     }
 
+    fn from_indicator(indicator: usize) -> Option<Self> {
+        match indicator {
+            0b0001 => Some(Self::Numeric),
+            0b0010 => Some(Self::Alphanumeric),
+            0b0100 => Some(Self::Bytes),
+            0b1000 => Some(Self::Kanji),
+            _ => None,
+        }
+    }
+
+    // never kanji, utf-8 text can pass for shift jis byte for byte
     pub fn best_mode(string: &[u8]) -> Self {
+        Self::pick(string, false)
+    }
+
+    // for input that's already shift jis, where kanji is worth trying
+    pub fn best_mode_sjis(string: &[u8]) -> Self {
+        Self::pick(string, true)
+    }
+
+    fn pick(string: &[u8], sjis: bool) -> Self {
         if !string.iter().any(|c| !(*c as char).is_ascii_digit()) {
             return Self::Numeric;
         }
@@ -35,8 +79,28 @@ impl Mode {
             return Self::Alphanumeric;
         }
 
+        if sjis && string.len().is_multiple_of(2) && string.chunks(2).all(|p| encode::kanji_value(p[0], p[1]).is_some()) {
+            return Self::Kanji;
+        }
+
         Self::Bytes
     }
+
+    pub const fn char_count(self, bytes: usize) -> usize {
+        match self {
+            Self::Kanji => bytes / 2,
+            _ => bytes,
+        }
+    }
+
+    const fn data_bit_length(self, chars: usize) -> usize {
+        match self {
+            Self::Numeric => chars / 3 * 10 + [0, 4, 7][chars % 3],
+            Self::Alphanumeric => chars / 2 * 11 + chars % 2 * 6,
+            Self::Bytes => chars * 8,
+            Self::Kanji => chars * 13,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -48,26 +112,38 @@ pub enum ErrorCorrectLv {
 pub struct Version(u8);
 
 impl Version {
-    pub fn new(v: u8) -> Self {
-        assert!((1..=40).contains(&v));
-        Self(v - 1)
+    pub const MIN: Self = Self(0);
+    pub const MAX: Self = Self(39);
+
+    pub const fn new(v: u8) -> Result<Self, Error> {
+        if v < 1 || v > 40 { return Err(Error::VersionOutOfRange(v)) };
+        Ok(Self(v - 1))
     }
 
-    pub fn version(self) -> u8 {
+    pub const fn version(self) -> u8 {
         self.0 + 1
     }
 
-    pub fn smallest_version(chars: usize, ec: ErrorCorrectLv, mode: Mode) -> Option<Self> {
-        for v in 0..40 {
+    pub const fn smallest_version(chars: usize, ec: ErrorCorrectLv, mode: Mode) -> Result<Self, Error> {
+        let mut v = 0;
+        while v < 40 {
             if VERSION_LIMITS[v * 4 + ec as usize][mode as usize] >= chars {
-                return Some(Self(v as _));
+                return Ok(Self(v as _));
             }
+            v += 1;
         }
 
-        None
+        Err(Error::DataTooLong {
+            needed: 4 + Self::MAX.char_count_length(mode) + mode.data_bit_length(chars),
+            capacity: Self::MAX.max_data_bytes(ec) * 8,
+        })
+    }
+
+    pub fn smallest_version_for(segments: &[Segment], ec: ErrorCorrectLv) -> Result<Self, Error> {
+        QrBuilder::new(ec).segments(segments).smallest_version()
     }
 
-    pub fn char_count_length(self, mode: Mode) -> usize {
+    pub const fn char_count_length(self, mode: Mode) -> usize {
         match self.version() {
             1..=9 => match mode {
                 Mode::Numeric => 10,
@@ -91,11 +167,11 @@ impl Version {
         }
     }
 
-    fn max_data_bytes(self, ec: ErrorCorrectLv) -> usize {
+    const fn max_data_bytes(self, ec: ErrorCorrectLv) -> usize {
         VERSION_BYTES[self.0 as usize * 4 + ec as usize][0]
     }
 
-    fn blocks_data(self, ec: ErrorCorrectLv) -> BlocksData {
+    const fn blocks_data(self, ec: ErrorCorrectLv) -> BlocksData {
         let d = &VERSION_BYTES[self.0 as usize * 4 + ec as usize];
 
         BlocksData {
@@ -108,7 +184,7 @@ impl Version {
     }
 }
 
-struct BlocksData {
+pub(crate) struct BlocksData {
     ec_bytes: usize,
     g1_blocks: usize,
     g1_bytes: usize,
@@ -116,6 +192,12 @@ struct BlocksData {
     g2_bytes: usize,
 }
 
+impl BlocksData {
+    const fn codewords(&self) -> usize {
+        self.g1_blocks * (self.g1_bytes + self.ec_bytes) + self.g2_blocks * (self.g2_bytes + self.ec_bytes)
+    }
+}
+
 static VERSION_LIMITS: [[usize; 4]; 40 * 4] = [
     [41, 25, 17, 10],
     [34, 20, 14, 8],
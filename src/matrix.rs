@@ -1,116 +1,196 @@
-use crate::{ErrorCorrectLv, Mode, Version};
+use alloc::{vec, vec::Vec};
+use core::ops::Range;
 
+use crate::builder::Part;
+use crate::penalty::{score_masks, scratch_len, Penalty, PenaltyReport, Row, WORDS};
+use crate::structure::interleave_into;
+use crate::{encode, Ansi, Error, ErrorCorrectLv, Mode, Segment, Version};
+
+// the bitmap is a vec unless it was generated into a borrowed buffer
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct QrMatrix {
-    bitmap: Vec<u8>,
-    size: usize,
-    stride: usize
+pub struct QrMatrix<B = Vec<u8>> {
+    pub(crate) bitmap: B,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) stride: usize,
+    // whatever generated the symbol fills these in, penalties only exist for full qr
+    pub(crate) mask: Option<usize>,
+    pub(crate) penalty: Option<Penalty>,
 }
 
 impl QrMatrix {
-    fn new(size: usize) -> Self {
-        Self {
-            bitmap: vec![0; ((size + 7) / 8) * size],
-            size,
-            stride: (size + 7) / 8,
-        }
+    pub(crate) fn new(width: usize, height: usize) -> Self {
+        Self::from_bitmap(vec![0; width.div_ceil(8) * height], width, height)
+    }
+
+    // bytes of buffer generate_into needs for a version
+    pub const fn buffer_len(version: Version) -> usize {
+        let size = version.0 as usize * 4 + 21;
+        size.div_ceil(8) * size
+    }
+
+    // and of its temp, room for the codewords twice over and the packed rows the masks are scored from
+    pub const fn temp_len(version: Version) -> usize {
+        Self::buffer_len(version) * 2 + scratch_len(version.0 as usize * 4 + 21)
+    }
+
+    pub fn generate(string: &[u8], mode: Mode, version: Version, ec: ErrorCorrectLv) -> Result<Self, Error> {
+        Ok(Self::from_data(&crate::encode::encode(string, mode, version, ec)?, version, ec, None))
+    }
+
+    pub fn generate_segments(segments: &[Segment], version: Version, ec: ErrorCorrectLv) -> Result<Self, Error> {
+        Ok(Self::from_data(&crate::encode::encode_segments(segments, version, ec)?, version, ec, None))
     }
 
-    pub fn generate(string: &[u8], mode: Mode, version: Version, ec: ErrorCorrectLv) -> Self {
-        let (mut mat, functions) = generate_unmasked_matrix(
-            version,
-            &crate::structure::structure(
-                &crate::encode::encode(string, mode, version, ec).unwrap(),
-                version,
-                ec
-            ),
-        );
-        let mask = apply_best_mask(&mut mat, &functions, version, ec);
+    // the best mask is picked unless one is forced
+    pub(crate) fn from_data(data: &[u8], version: Version, ec: ErrorCorrectLv, mask: Option<usize>) -> Self {
+        let size = version.0 as usize * 4 + 21;
+        let mut mat = UnfinishedMatrix::new(size, size, Some(6));
+        place_symbol(&mut mat, version, ec, &crate::structure::structure(data, version, ec), mask, &mut vec![0; scratch_len(size)]);
+
+        mat.matrix
+    }
 
+    pub(crate) fn unmask(&self, version: Version, ec: ErrorCorrectLv, mask: usize) -> Self {
+        let mut mat = self.clone();
+        apply_mask(&mut mat, &function_patterns(version).done, version, ec, mask);
         mat
     }
 
-    pub fn get(&self, x: usize, y: usize) -> bool {
-        let b = self.bitmap[x / 8 + y * self.stride];
-        b & (1 << (x % 8)) != 0
+    pub(crate) fn read_codewords(&self, version: Version, count: usize) -> Vec<u8> {
+        let mut modules = version.data_modules();
+        (0..count).map(|_| modules.by_ref().take(8).fold(0, |b, (x, y)| b << 1 | self.get(x, y) as u8)).collect()
     }
 
-    fn set(&mut self, x: usize, y: usize, v: bool) {
-        self.bitmap[x / 8 + y * self.stride] &= !(1 << x % 8);
-        self.bitmap[x / 8 + y * self.stride] |= (v as u8) << x % 8;
+    // scores all eight masks over this symbol's data, whichever encoder made it
+    pub fn penalty_report(&self) -> Result<PenaltyReport, Error> {
+        let version = read_version(self).ok_or(Error::InvalidVersionInfo)?;
+        let (ec, mask) = read_format(self).ok_or(Error::InvalidFormatInfo)?;
+
+        let scratch = &mut vec![0; scratch_len(self.size())];
+        let penalties = score_masks(&self.unmask(version, ec, mask), &function_patterns(version).done, version, ec, 0..8, scratch);
+        Ok(PenaltyReport { penalties, mask })
     }
+}
 
-    pub fn size(&self) -> usize { self.size }
+impl<'a> QrMatrix<&'a mut [u8]> {
+    // no allocations, the matrix lives in buffer and temp is scratch space for the codewords and the mask scoring
+    pub fn generate_into(segments: &[Segment], version: Version, ec: ErrorCorrectLv, buffer: &'a mut [u8], temp: &mut [u8]) -> Result<Self, Error> {
+        let len = QrMatrix::buffer_len(version);
+        if buffer.len() < len { return Err(Error::BufferTooSmall { needed: len, actual: buffer.len() }) };
+        let temp_len = QrMatrix::temp_len(version);
+        if temp.len() < temp_len { return Err(Error::BufferTooSmall { needed: temp_len, actual: temp.len() }) };
+
+        let blocks_data = version.blocks_data(ec);
+        let (codeword_halves, scratch) = temp[..temp_len].split_at_mut(len * 2);
+        let (data, codewords) = codeword_halves.split_at_mut(len);
+        let codewords = &mut codewords[..blocks_data.codewords()];
+
+        encode::encode_into(segments.iter().map(|s| Part::Segment(*s)), version, ec, &mut data[..version.max_data_bytes(ec)])?;
+        interleave_into(data, &blocks_data, codewords);
+
+        // the data half is free again once it's interleaved, it keeps track of the function patterns instead
+        let size = version.0 as usize * 4 + 21;
+        let mut mat = UnfinishedMatrix {
+            matrix: QrMatrix::from_bitmap(&mut buffer[..len], size, size),
+            done: QrMatrix::from_bitmap(data, size, size),
+            timing: Some(6),
+        };
+        place_symbol(&mut mat, version, ec, codewords, None, scratch);
+
+        // the matrix only borrowed the buffer for as long as temp, hand it back for as long as the caller lent it
+        let UnfinishedMatrix { matrix: QrMatrix { width, height, stride, mask, penalty, .. }, .. } = mat;
+        Ok(Self { bitmap: &mut buffer[..len], width, height, stride, mask, penalty })
+    }
 }
 
-impl core::fmt::Display for QrMatrix {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "\x1b[38;5;255m")?;
-        for _ in 0..4 {
-            writeln!(f, "{:█<1$}", "", self.size() * 2 + 16)?;
-        }
+impl<B: AsRef<[u8]>> QrMatrix<B> {
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        module(self.bitmap.as_ref(), self.stride, x, y)
+    }
 
-        for y in 0..self.size() {
-            write!(f, "\x1b[38;5;255m████████")?;
-            for b in 0..self.stride {
-                let mut x = self.bitmap[y * self.stride + b];
+    // the side of a square symbol, rmqr needs width and height
+    pub fn size(&self) -> usize { self.width }
 
-                for i in 0..8 {
-                    if b * 8 + i >= self.size() { break };
+    pub fn width(&self) -> usize { self.width }
 
-                    write!(f, "\x1b[38;5;{}m██", if x & 1 != 0 { "232" } else { "255" })?;
-                    x >>= 1;
-                }
-            }
-            writeln!(f, "\x1b[38;5;255m████████")?;
-        }
+    pub fn height(&self) -> usize { self.height }
 
-        write!(f, "\x1b[38;5;255m")?;
-        for _ in 0..4 {
-            writeln!(f, "{:█<1$}", "", self.size() * 2 + 16)?;
+    // none when the matrix didn't come from a generator, like a scanned one
+    pub fn mask(&self) -> Option<usize> { self.mask }
+
+    // the penalty of the chosen mask, only for full qr
+    pub fn penalty(&self) -> Option<Penalty> { self.penalty }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> QrMatrix<B> {
+    pub(crate) fn from_bitmap(mut bitmap: B, width: usize, height: usize) -> Self {
+        bitmap.as_mut().fill(0);
+        Self {
+            bitmap,
+            width,
+            height,
+            stride: width.div_ceil(8),
+            mask: None,
+            penalty: None,
         }
+    }
 
-        write!(f, "\x1b[0m")
+    pub(crate) fn set(&mut self, x: usize, y: usize, v: bool) {
+        let bitmap = self.bitmap.as_mut();
+        bitmap[x / 8 + y * self.stride] &= !(1 << (x % 8));
+        bitmap[x / 8 + y * self.stride] |= (v as u8) << (x % 8);
     }
 }
 
-struct UnfinishedMatrix {
-    matrix: QrMatrix,
-    done: QrMatrix,
+impl core::fmt::Display for QrMatrix {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.render(&Ansi::new()))
+    }
+}
+
+pub(crate) struct UnfinishedMatrix<B = Vec<u8>> {
+    pub(crate) matrix: QrMatrix<B>,
+    pub(crate) done: QrMatrix<B>,
+    // the vertical timing column the data zigzag steps over, if it isn't already between column pairs
+    timing: Option<usize>,
 }
 
 impl UnfinishedMatrix {
-    fn new(size: usize) -> Self {
+    pub(crate) fn new(width: usize, height: usize, timing: Option<usize>) -> Self {
         Self {
-            matrix: QrMatrix::new(size),
-            done: QrMatrix::new(size),
+            matrix: QrMatrix::new(width, height),
+            done: QrMatrix::new(width, height),
+            timing,
         }
     }
+}
 
+impl<B: AsRef<[u8]> + AsMut<[u8]>> UnfinishedMatrix<B> {
     fn get(&self, x: usize, y: usize) -> Option<bool> {
         self.done.get(x, y).then(|| self.matrix.get(x, y))
     }
 
-    fn set(&mut self, x: usize, y: usize, v: bool) {
+    pub(crate) fn set(&mut self, x: usize, y: usize, v: bool) {
         if !self.done.get(x, y) {
             self.done.set(x, y, true);
             self.matrix.set(x, y, v);
         }
     }
 
-    fn set_hline(&mut self, x: usize, y: usize, dist: usize, v: bool) {
+    pub(crate) fn set_hline(&mut self, x: usize, y: usize, dist: usize, v: bool) {
         for x in x..x + dist {
             self.set(x, y, v);
         }
     }
 
-    fn set_vline(&mut self, x: usize, y: usize, dist: usize, v: bool) {
+    pub(crate) fn set_vline(&mut self, x: usize, y: usize, dist: usize, v: bool) {
         for y in y..y + dist {
             self.set(x, y, v);
         }
     }
 
-    fn set_filled_box(&mut self, x: usize, y: usize, sx: usize, sy: usize, v: bool) {
+    pub(crate) fn set_filled_box(&mut self, x: usize, y: usize, sx: usize, sy: usize, v: bool) {
         for y in y..y + sy {
             for x in x..x + sx {
                 self.set(x, y, v);
@@ -118,7 +198,7 @@ impl UnfinishedMatrix {
         }
     }
 
-    fn set_outline_box(&mut self, x: usize, y: usize, sx: usize, sy: usize, v: bool) {
+    pub(crate) fn set_outline_box(&mut self, x: usize, y: usize, sx: usize, sy: usize, v: bool) {
         for x in x..x + sx {
             self.set(x, y, v);
             self.set(x, y + sy - 1, v);
@@ -130,17 +210,23 @@ impl UnfinishedMatrix {
     }
 }
 
-fn generate_unmasked_matrix(version: Version, data: &[u8]) -> (QrMatrix, QrMatrix) {
+pub(crate) fn function_patterns(version: Version) -> UnfinishedMatrix {
     let size = version.0 as usize * 4 + 21;
-    let mut mat = UnfinishedMatrix::new(size);
+    let mut mat = UnfinishedMatrix::new(size, size, Some(6));
+    place_function_patterns(&mut mat, version);
+    mat
+}
 
-    place_finder(&mut mat, 0, 0);
-    place_finder(&mut mat, size - 7, 0);
-    place_finder(&mut mat, 0, size - 7);
+fn place_function_patterns<B: AsRef<[u8]> + AsMut<[u8]>>(mat: &mut UnfinishedMatrix<B>, version: Version) {
+    let size = mat.matrix.size();
+
+    place_finder(mat, 0, 0);
+    place_finder(mat, size - 7, 0);
+    place_finder(mat, 0, size - 7);
 
     for y in ALIGN_LOCATIONS[version.0 as usize] {
         for x in ALIGN_LOCATIONS[version.0 as usize] {
-            place_alignment(&mut mat, *x, *y);
+            place_alignment(mat, *x, *y);
         }
     }
 
@@ -163,22 +249,30 @@ fn generate_unmasked_matrix(version: Version, data: &[u8]) -> (QrMatrix, QrMatri
         mat.set_filled_box(size - 11, 0, 3, 6, false);
         mat.set_filled_box(0, size - 11, 6, 3, false);
     }
+}
 
-    let functions = mat.done.clone();
+// everything but the data is left in done, which doubles as the function pattern map for masking
+fn place_symbol<B: AsRef<[u8]> + AsMut<[u8]>>(mat: &mut UnfinishedMatrix<B>, version: Version, ec: ErrorCorrectLv, codewords: &[u8], mask: Option<usize>, scratch: &mut [u8]) {
+    place_function_patterns(mat, version);
 
-    // data placement
-    let mut cursor = (size - 1, size - 1, true, true);
-    for mut b in data.iter().copied() {
-        for _ in 0..8 {
-            place_data(&mut mat, &mut cursor, b & 0x80 != 0);
-            b <<= 1;
+    // data placement, the remainder bits stay light
+    let mut modules = version.data_modules();
+    for b in codewords {
+        for (i, (x, y)) in modules.by_ref().take(8).enumerate() {
+            mat.matrix.set(x, y, b << i & 0x80 != 0);
         }
     }
 
-    (mat.matrix, functions)
+    let masks = match mask {
+        Some(m) => m..m + 1,
+        None => 0..8,
+    };
+    let (mask, penalty) = apply_best_mask(&mut mat.matrix, &mat.done, version, ec, masks, scratch);
+    mat.matrix.mask = Some(mask);
+    mat.matrix.penalty = Some(penalty);
 }
 
-fn place_finder(mat: &mut UnfinishedMatrix, x: usize, y: usize) {
+pub(crate) fn place_finder<B: AsRef<[u8]> + AsMut<[u8]>>(mat: &mut UnfinishedMatrix<B>, x: usize, y: usize) {
     mat.set_outline_box(x, y, 7, 7, true);
     mat.set_outline_box(x + 1, y + 1, 5, 5, false);
     mat.set_filled_box(x + 2, y + 2, 3, 3, true);
@@ -189,7 +283,7 @@ fn place_finder(mat: &mut UnfinishedMatrix, x: usize, y: usize) {
     mat.set(if x == 0 { 7 } else { x - 1 }, if y == 0 { 7 } else { y - 1 }, false);
 }
 
-fn place_alignment(mat: &mut UnfinishedMatrix, x: usize, y: usize) {
+fn place_alignment<B: AsRef<[u8]> + AsMut<[u8]>>(mat: &mut UnfinishedMatrix<B>, x: usize, y: usize) {
     if mat.done.get(x, y) { return };
 
     mat.set_outline_box(x - 2, y - 2, 5, 5, true);
@@ -197,263 +291,390 @@ fn place_alignment(mat: &mut UnfinishedMatrix, x: usize, y: usize) {
     mat.set(x, y, true);
 }
 
-fn place_data(mat: &mut UnfinishedMatrix, cursor: &mut (usize, usize, bool, bool), data: bool) {
-    mat.set(cursor.0, cursor.1, data);
+impl Version {
+    pub const fn data_modules(self) -> DataModules {
+        let size = self.0 as usize * 4 + 21;
+        let pair = pair_modules(size - 1, true, self);
+        DataModules { version: self, right: size - 1, upward: true, pair, word: PAIR_WORDS - 1, bits: pair[PAIR_WORDS - 1] }
+    }
+}
 
-    while mat.get(cursor.0, cursor.1).is_some() {
-        if cursor.2 {
-            // upward
-            if cursor.3 {
-                // at right
-                cursor.0 -= 1;
-                cursor.3 = false;
+// the data modules of a version in placement order: two columns at a time from the right, up and
+// down in turn, over the timing column and around the function patterns
+#[derive(Debug, Clone)]
+pub struct DataModules {
+    version: Version,
+    // right column of the current pair, the walk is through once the pair at 1 runs out
+    right: usize,
+    upward: bool,
+    // the pair's data modules from pair_modules, and what's left of the word the walk is in
+    pair: [u64; PAIR_WORDS],
+    word: usize,
+    bits: u64,
+}
+
+impl DataModules {
+    // next without the trait, for generate_const
+    pub(crate) const fn next_const(&mut self) -> Option<(usize, usize)> {
+        while self.bits == 0 {
+            // going up is going down the bits, from the last word
+            let last = if self.upward { 0 } else { PAIR_WORDS - 1 };
+            if self.word != last {
+                self.word = if self.upward { self.word - 1 } else { self.word + 1 };
+            } else if self.right > 1 {
+                self.right -= if self.right == 8 { 3 } else { 2 };
+                self.upward = !self.upward;
+                self.pair = pair_modules(self.right, self.upward, self.version);
+                self.word = if self.upward { PAIR_WORDS - 1 } else { 0 };
             } else {
-                // at left
-                if cursor.1 == 0 {
-                    // at edge
-                    if cursor.0 == 0 { break };
-                    cursor.0 -= 1 + (cursor.0 == 7) as usize;
-                    cursor.2 = false;
-                } else {
-                    cursor.0 += 1;
-                    cursor.1 -= 1;
-                    cursor.3 = true;
-                }
-            }
-        } else {
-            // downward
-            if cursor.3 {
-                // at left
-                if cursor.1 >= mat.matrix.size() - 1 {
-                    // at edge
-                    if cursor.0 == 0 { break };
-                    cursor.0 -= 1 + (cursor.0 == 7) as usize;
-                    cursor.2 = true;
-                } else {
-                    cursor.0 += 1;
-                    cursor.1 += 1;
-                    cursor.3 = false;
-                }
-            } else {
-                // at right
-                cursor.0 -= 1;
-                cursor.3 = true;
+                return None;
             }
+            self.bits = self.pair[self.word];
         }
+
+        let bit = if self.upward { 63 - self.bits.leading_zeros() } else { self.bits.trailing_zeros() } as usize;
+        self.bits &= !(1 << bit);
+
+        let k = self.word * 64 + bit;
+        let left = if self.upward { 1 - k % 2 } else { k % 2 };
+        Some((self.right - left, k / 2))
     }
 }
 
-fn apply_best_mask(mat: &mut QrMatrix, functions: &QrMatrix, version: Version, ec: ErrorCorrectLv) -> usize {
-    let mut try_mat = mat.clone();
-    let mut best_mask = 0;
-    let mut best_penalty = usize::MAX;
+const PAIR_WORDS: usize = 177 * 2 / 64 + 1;
 
-    for m in 0..8 {
-        apply_mask(&mut try_mat, functions, version, ec, m);
-        let penalty = calculate_penalty(&try_mat);
+// the data modules of a column pair with the rows interleaved, bit 2y for the column the walk takes first
+// in row y and 2y + 1 for the other, which is the right one going down and the left one going up since
+// that walks the bits backwards
+const fn pair_modules(right: usize, upward: bool, version: Version) -> [u64; PAIR_WORDS] {
+    let size = version.0 as usize * 4 + 21;
+    let (even, odd) = if upward { (right - 1, right) } else { (right, right - 1) };
+    let (even, odd) = (function_rows(even, version), function_rows(odd, version));
+
+    let mut pair = [0; PAIR_WORDS];
+    let mut i = 0;
+    while i < PAIR_WORDS {
+        let rows = size.saturating_sub(i * 32);
+        let inside = if rows >= 32 { u32::MAX } else { (1 << rows) - 1 };
+        let (even, odd) = (!(even[i / 2] >> (i % 2 * 32)) as u32 & inside, !(odd[i / 2] >> (i % 2 * 32)) as u32 & inside);
+        pair[i] = spread(even) | spread(odd) << 1;
+        i += 1;
+    }
+    pair
+}
 
-        if best_penalty > penalty {
-            best_mask = m;
-            best_penalty = penalty;
-        }
+// bit i to bit 2i
+const fn spread(bits: u32) -> u64 {
+    let mut bits = bits as u64;
+    bits = (bits | bits << 16) & 0x0000_ffff_0000_ffff;
+    bits = (bits | bits << 8) & 0x00ff_00ff_00ff_00ff;
+    bits = (bits | bits << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    bits = (bits | bits << 2) & 0x3333_3333_3333_3333;
+    (bits | bits << 1) & 0x5555_5555_5555_5555
+}
+
+// the rows of column x that function_module covers, as bits, worked out a pattern at a time
+const fn function_rows(x: usize, version: Version) -> Row {
+    let size = version.0 as usize * 4 + 21;
+    let mut rows = [0; WORDS];
+    if x == 6 { return set_rows(rows, 0, size) };
+
+    // timing, then the finders with their separators and the format info next to them, and the dark module
+    rows = set_rows(rows, 6, 7);
+    if x < 9 {
+        rows = set_rows(set_rows(rows, 0, 9), size - 8, size);
+    } else if x >= size - 8 {
+        rows = set_rows(rows, 0, 9);
+    }
 
-        try_mat.bitmap.copy_from_slice(&mat.bitmap);
+    if version.version() >= 7 {
+        if x >= size - 11 && x < size - 8 { rows = set_rows(rows, 0, 6) };
+        if x < 6 { rows = set_rows(rows, size - 11, size - 8) };
     }
 
-    apply_mask(mat, functions, version, ec, best_mask);
-    best_mask
-}
-
-fn calculate_penalty(mat: &QrMatrix) -> usize {
-    let mut penalty = 0;
-    let mut black_count = 0;
-
-    // 5 in a row
-    let mut count = 0;
-    let mut color = false;
-    for y in 0..mat.size() {
-        for x in 0..mat.size() {
-            let c = mat.get(x, y);
-            black_count += c as usize;
-            if color != c {
-                count = 0;
-                color = c;
-            }
-            count += 1;
+    // alignments, except the ones that would overlap a finder
+    let cx = nearest_alignment(x, version);
+    if x.abs_diff(cx) > 2 { return rows };
+
+    let locations = ALIGN_LOCATIONS[version.0 as usize];
+    let mut j = 0;
+    while j < locations.len() {
+        let cy = locations[j];
+        let in_finder = (cx < 8 || cx >= size - 8) && cy < 8 || cx < 8 && cy >= size - 8;
+        if !in_finder { rows = set_rows(rows, cy - 2, cy + 3) };
+        j += 1;
+    }
+    rows
+}
 
-            if count == 5 {
-                penalty += 3;
-            } else if count > 5 {
-                penalty += 1;
-            }
-        }
+const fn set_rows(mut rows: Row, from: usize, to: usize) -> Row {
+    let mut y = from;
+    while y < to {
+        rows[y / 64] |= 1 << (y % 64);
+        y += 1;
+    }
+    rows
+}
+
+impl Iterator for DataModules {
+    type Item = (usize, usize);
 
-        count = 0;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_const()
     }
+}
 
-    // 5 in a column
-    for x in 0..mat.size() {
-        for y in 0..mat.size() {
-            let c = mat.get(x, y);
-            if color != c {
-                count = 0;
-                color = c;
-            }
-            count += 1;
+// data modules aren't marked done, the zigzag never comes back to them
+pub(crate) fn place_data<B: AsRef<[u8]> + AsMut<[u8]>>(mat: &mut UnfinishedMatrix<B>, cursor: &mut (usize, usize, bool, bool), data: bool) {
+    mat.matrix.set(cursor.0, cursor.1, data);
+    if step(mat, cursor) { skip_filled(mat, cursor) };
+}
 
-            if count == 5 {
-                penalty += 3;
-            } else if count > 5 {
-                penalty += 1;
+// moves the cursor along the zigzag to the next module that's still empty
+pub(crate) fn skip_filled<B: AsRef<[u8]> + AsMut<[u8]>>(mat: &UnfinishedMatrix<B>, cursor: &mut (usize, usize, bool, bool)) {
+    while mat.get(cursor.0, cursor.1).is_some() && step(mat, cursor) {}
+}
+
+// one module along the zigzag, false once it has run out
+fn step<B: AsRef<[u8]>>(mat: &UnfinishedMatrix<B>, cursor: &mut (usize, usize, bool, bool)) -> bool {
+    if cursor.2 {
+        // upward
+        if cursor.3 {
+            // at right
+            cursor.0 -= 1;
+            cursor.3 = false;
+        } else {
+            // at left
+            if cursor.1 == 0 {
+                // at edge
+                let step = 1 + mat.timing.is_some_and(|t| cursor.0 == t + 1) as usize;
+                if cursor.0 < step { return false };
+                cursor.0 -= step;
+                cursor.2 = false;
+            } else {
+                cursor.0 += 1;
+                cursor.1 -= 1;
+                cursor.3 = true;
+            }
+        }
+    } else {
+        // downward
+        if cursor.3 {
+            // at left
+            if cursor.1 >= mat.matrix.height() - 1 {
+                // at edge
+                let step = 1 + mat.timing.is_some_and(|t| cursor.0 == t + 1) as usize;
+                if cursor.0 < step { return false };
+                cursor.0 -= step;
+                cursor.2 = true;
+            } else {
+                cursor.0 += 1;
+                cursor.1 += 1;
+                cursor.3 = false;
             }
+        } else {
+            // at right
+            cursor.0 -= 1;
+            cursor.3 = true;
         }
+    }
 
-        count = 0;
+    true
+}
+
+// what place_function_patterns draws at a module, in the order it draws it, or None for data
+pub(crate) const fn function_module(x: usize, y: usize, version: Version) -> Option<bool> {
+    let size = version.0 as usize * 4 + 21;
+
+    // finders with their separators
+    let finder = if x < 8 && y < 8 {
+        Some((3, 3))
+    } else if x >= size - 8 && y < 8 {
+        Some((size - 4, 3))
+    } else if x < 8 && y >= size - 8 {
+        Some((3, size - 4))
+    } else {
+        None
+    };
+    if let Some((cx, cy)) = finder {
+        let d = distance(x, y, cx, cy);
+        return Some(d != 2 && d != 4);
     }
 
-    // 2x2 overlapping squares
-    for y in 0..mat.size() - 1 {
-        for x in 0..mat.size() - 1 {
-            let tl = mat.get(x, y);
-            let tr = mat.get(x + 1, y);
-            let bl = mat.get(x, y + 1);
-            let br = mat.get(x + 1, y + 1);
+    // alignments, except the ones that would overlap a finder
+    let (cx, cy) = (nearest_alignment(x, version), nearest_alignment(y, version));
+    let in_finder = (cx < 8 || cx >= size - 8) && cy < 8 || cx < 8 && cy >= size - 8;
+    if !in_finder && distance(x, y, cx, cy) <= 2 {
+        return Some(distance(x, y, cx, cy) != 1);
+    }
 
-            if tl == tr && tr == bl && bl == br {
-                penalty += 3;
-            }
-        }
+    // timing
+    if y == 6 { return Some(x & 1 == 0) };
+    if x == 6 { return Some(y & 1 == 0) };
+
+    // dark module
+    if x == 8 && y == size - 8 { return Some(true) };
+
+    // reserved for the format and version info
+    if y < 9 && (x < 9 || x >= size - 8) || x < 9 && y >= size - 8 {
+        return Some(false);
+    }
+    if version.version() >= 7 && (x >= size - 11 && x < size - 8 && y < 6 || y >= size - 11 && y < size - 8 && x < 6) {
+        return Some(false);
     }
 
-    // finder 1-1-3-1-1 patterns in rows
-    for y in 0..mat.size() {
-        for x in 0..mat.size() - 6 {
-            let a = mat.get(x, y);
-            let b = mat.get(x + 1, y);
-            let c = mat.get(x + 2, y);
-            let d = mat.get(x + 3, y);
-            let e = mat.get(x + 4, y);
-            let f = mat.get(x + 5, y);
-            let g = mat.get(x + 6, y);
-
-            if a && !b && c && d && e && !f && g {
-                if (
-                    x >= 4
-                    && !mat.get(x - 1, y)
-                    && !mat.get(x - 2, y)
-                    && !mat.get(x - 3, y)
-                    && !mat.get(x - 4, y)
-                ) || (
-                    x + 10 < mat.size()
-                    && !mat.get(x + 7, y)
-                    && !mat.get(x + 8, y)
-                    && !mat.get(x + 9, y)
-                    && !mat.get(x + 10, y)
-                ) {
-                    penalty += 40;
-                }
-            }
-        }
+    None
+}
+
+// alignment coordinate closest to i, there's only ever one within 2
+const fn nearest_alignment(i: usize, version: Version) -> usize {
+    let locations = ALIGN_LOCATIONS[version.0 as usize];
+    let mut nearest = usize::MAX;
+    let mut j = 0;
+    while j < locations.len() {
+        if locations[j].abs_diff(i) < nearest.abs_diff(i) { nearest = locations[j] };
+        j += 1;
     }
+    nearest
+}
 
-    // finder 1-1-3-1-1 patterns in columns
-    for x in 0..mat.size() {
-        for y in 0..mat.size() - 6 {
-            let a = mat.get(x, y);
-            let b = mat.get(x, y + 1);
-            let c = mat.get(x, y + 2);
-            let d = mat.get(x, y + 3);
-            let e = mat.get(x, y + 4);
-            let f = mat.get(x, y + 5);
-            let g = mat.get(x, y + 6);
-
-            if a && !b && c && d && e && !f && g {
-                if (
-                    y >= 4
-                    && !mat.get(x, y - 1)
-                    && !mat.get(x, y - 2)
-                    && !mat.get(x, y - 3)
-                    && !mat.get(x, y - 4)
-                ) || (
-                    y + 10 < mat.size()
-                    && !mat.get(x, y + 7)
-                    && !mat.get(x, y + 8)
-                    && !mat.get(x, y + 9)
-                    && !mat.get(x, y + 10)
-                ) {
-                    penalty += 40;
-                }
-            }
+const fn distance(x: usize, y: usize, cx: usize, cy: usize) -> usize {
+    let (dx, dy) = (x.abs_diff(cx), y.abs_diff(cy));
+    if dx > dy { dx } else { dy }
+}
+
+pub(crate) fn apply_best_mask<B: AsRef<[u8]> + AsMut<[u8]>>(mat: &mut QrMatrix<B>, functions: &QrMatrix<B>, version: Version, ec: ErrorCorrectLv, masks: Range<usize>, scratch: &mut [u8]) -> (usize, Penalty) {
+    let penalties = score_masks(mat, functions, version, ec, masks.clone(), scratch);
+
+    // min_by_key keeps the first of equals, so ties go to the lowest mask
+    let best_mask = masks.min_by_key(|m| penalties[*m].total()).unwrap();
+    apply_mask(mat, functions, version, ec, best_mask);
+    (best_mask, penalties[best_mask])
+}
+
+pub(crate) const fn module(bitmap: &[u8], stride: usize, x: usize, y: usize) -> bool {
+    bitmap[x / 8 + y * stride] & (1 << (x % 8)) != 0
+}
+
+fn apply_mask<B: AsRef<[u8]> + AsMut<[u8]>>(mat: &mut QrMatrix<B>, functions: &QrMatrix<impl AsRef<[u8]>>, version: Version, ec: ErrorCorrectLv, mask: usize) {
+    flip_mask(mat, functions, mask);
+    place_format_and_version(mat, version, ec, mask);
+}
+
+// a byte of the mask at a time, out of the same rows the penalty scoring packs
+pub(crate) fn flip_mask<B: AsRef<[u8]> + AsMut<[u8]>>(mat: &mut QrMatrix<B>, functions: &QrMatrix<impl AsRef<[u8]>>, mask: usize) {
+    let (stride, width) = (mat.stride, mat.width);
+    let rows = mat.bitmap.as_mut().chunks_mut(stride).zip(functions.bitmap.as_ref().chunks(stride));
+    for (y, (row, functions)) in rows.enumerate() {
+        for (i, (b, f)) in row.iter_mut().zip(functions).enumerate() {
+            // the padding past the last module stays clear
+            let inside = 0xff >> (8 - (width - i * 8).min(8));
+            *b ^= (MASK_ROWS[mask][y % 12][i / 8] >> (i % 8 * 8)) as u8 & !f & inside;
         }
     }
+}
 
-    // black ratio
-    let percentage = black_count * 100 / (mat.size() * mat.size());
-    let prev = (percentage / 5 * 5) as isize;
-    penalty += (prev - 50).abs().min((prev - 45).abs()) as usize * 2;
-
-    penalty
-}
-
-fn apply_mask(mat: &mut QrMatrix, functions: &QrMatrix, version: Version, ec: ErrorCorrectLv, mask: usize) {
-    for y in 0..mat.size() {
-        for x in 0..mat.size() {
-            if !functions.get(x, y) && match mask {
-                0 => (x + y) % 2,
-                1 => y % 2,
-                2 => x % 3,
-                3 => (x + y) % 3,
-                4 => (y / 2 + x / 3) % 2,
-                5 => x * y % 2 + x * y % 3,
-                6 => (x * y % 2 + x * y % 3) % 2,
-                7 => ((x + y) % 2 + x * y % 3) % 2,
-                _ => panic!(),
-            } == 0 {
-                mat.set(x, y, !mat.get(x, y));
-            }
-        }
+// masked() only depends on x % 6 and y % 12, so the mask of a row is one of 12 for each mask
+pub(crate) static MASK_ROWS: [[Row; 12]; 8] = mask_rows();
+
+const fn mask_rows() -> [[Row; 12]; 8] {
+    let mut rows = [[[0; WORDS]; 12]; 8];
+    let mut i = 0;
+    while i < 8 * 12 * WORDS * 64 {
+        let (m, y, x) = (i / (12 * WORDS * 64), i / (WORDS * 64) % 12, i % (WORDS * 64));
+        rows[m][y][x / 64] |= (masked(m, x, y) as u64) << (x % 64);
+        i += 1;
     }
+    rows
+}
 
-    place_format_and_version(mat, version, ec, mask);
+pub(crate) const fn masked(mask: usize, x: usize, y: usize) -> bool {
+    let v = match mask {
+        0 => (x + y) % 2,
+        1 => y % 2,
+        2 => x % 3,
+        3 => (x + y) % 3,
+        4 => (y / 2 + x / 3) % 2,
+        5 => x * y % 2 + x * y % 3,
+        6 => (x * y % 2 + x * y % 3) % 2,
+        7 => ((x + y) % 2 + x * y % 3) % 2,
+        _ => panic!(),
+    };
+
+    v == 0
 }
 
-fn place_format_and_version(mat: &mut QrMatrix, version: Version, ec: ErrorCorrectLv, mask: usize) {
+fn place_format_and_version<B: AsRef<[u8]> + AsMut<[u8]>>(mat: &mut QrMatrix<B>, version: Version, ec: ErrorCorrectLv, mask: usize) {
+    for (x, y, v) in format_and_version(mat.size(), version, ec, mask) {
+        mat.set(x, y, v);
+    }
+}
+
+pub(crate) fn format_and_version(size: usize, version: Version, ec: ErrorCorrectLv, mask: usize) -> impl Iterator<Item = (usize, usize, bool)> {
     let format = FORMAT_INFO[ec as usize * 8 + mask];
+    let format = (0..15).flat_map(move |i| format_positions(size, i).map(|(x, y)| (x, y, (format << i) & 0x4000 != 0)));
 
-    for i in 0..=5 {
-        mat.set(i, 8, (format << i) & 0x4000 != 0);
-        mat.set(8, mat.size() - i - 1, (format << i) & 0x4000 != 0);
-    }
-    mat.set(7, 8, (format << 6) & 0x4000 != 0);
-    mat.set(8, mat.size() - 6 - 1, (format << 6) & 0x4000 != 0);
+    // low bit first, three to a row of one block and a column of the other
+    let info = VERSION_INFO[version.0 as usize];
+    let version = (0..if version.version() < 7 { 0 } else { 18 }).flat_map(move |i| {
+        let v = info >> i & 1 != 0;
+        [(size - 11 + i % 3, i / 3, v), (i / 3, size - 11 + i % 3, v)]
+    });
 
-    for i in 7..=8 {
-        mat.set(8, 15 - i, (format << i) & 0x4000 != 0);
-        mat.set(mat.size() - 15 + i, 8, (format << i) & 0x4000 != 0);
+    format.chain(version)
+}
+
+// both copies of format bit i, counted from the most significant one
+pub(crate) const fn format_positions(size: usize, i: usize) -> [(usize, usize); 2] {
+    match i {
+        0..=5 => [(i, 8), (8, size - i - 1)],
+        6 => [(7, 8), (8, size - 7)],
+        7..=8 => [(8, 15 - i), (size - 15 + i, 8)],
+        _ => [(8, 14 - i), (size - 15 + i, 8)],
     }
+}
 
-    for i in 9..=14 {
-        mat.set(8, 14 - i, (format << i) & 0x4000 != 0);
-        mat.set(mat.size() - 15 + i, 8, (format << i) & 0x4000 != 0);
+pub(crate) fn read_format(mat: &QrMatrix) -> Option<(ErrorCorrectLv, usize)> {
+    let mut copies = [0_u32; 2];
+    for i in 0..15 {
+        for (copy, (x, y)) in copies.iter_mut().zip(format_positions(mat.size(), i)) {
+            *copy = *copy << 1 | mat.get(x, y) as u32;
+        }
     }
 
-    if version.version() < 7 { return };
+    // the format codes are at least 7 bits apart, so anything within 3 is still unambiguous
+    let (i, distance) = nearest(&copies, FORMAT_INFO.iter().map(|f| *f as u32))?;
+    if distance > 3 { return None };
 
-    let mut version = VERSION_INFO[version.0 as usize];
+    let ec = [ErrorCorrectLv::L, ErrorCorrectLv::M, ErrorCorrectLv::Q, ErrorCorrectLv::H][i / 8];
+    Some((ec, i % 8))
+}
 
-    for i in 0..6 {
-        for j in 0..3 {
-            let v = version & 1 != 0;
+pub(crate) fn read_version(mat: &QrMatrix) -> Option<Version> {
+    if mat.width() != mat.height() || mat.size() < 21 || !(mat.size() - 21).is_multiple_of(4) { return None };
 
-            mat.set(mat.size() + j - 11, i, v);
-            mat.set(i, mat.size() + j - 11, v);
+    let version = Version(((mat.size() - 21) / 4) as u8);
+    if version.version() < 7 { return Some(version) };
 
-            version >>= 1;
+    let mut copies = [0_u32; 2];
+    for i in (0..6).rev() {
+        for j in (0..3).rev() {
+            copies[0] = copies[0] << 1 | mat.get(mat.size() + j - 11, i) as u32;
+            copies[1] = copies[1] << 1 | mat.get(i, mat.size() + j - 11) as u32;
         }
     }
+
+    let (i, distance) = nearest(&copies, VERSION_INFO.iter().copied().skip(6))?;
+    (distance <= 3 && i + 6 == version.0 as usize).then_some(version)
+}
+
+fn nearest(copies: &[u32], codes: impl Iterator<Item = u32> + Clone) -> Option<(usize, u32)> {
+    copies.iter()
+        .flat_map(|c| codes.clone().map(move |code| (c ^ code).count_ones()).enumerate())
+        .min_by_key(|(_, d)| *d)
 }
 
-static ALIGN_LOCATIONS: [&[usize]; 40] = [
+pub(crate) static ALIGN_LOCATIONS: [&[usize]; 40] = [
     &[],
     &[6, 18],
     &[6, 22],
@@ -497,7 +718,7 @@ static ALIGN_LOCATIONS: [&[usize]; 40] = [
 ];
 
 // https://www.thonky.com/qr-code-tutorial/format-version-tables
-static FORMAT_INFO: [u16; 4 * 8] = [
+pub(crate) static FORMAT_INFO: [u16; 4 * 8] = [
     0b111011111000100,
     0b111001011110011,
     0b111110110101010,
@@ -532,7 +753,7 @@ static FORMAT_INFO: [u16; 4 * 8] = [
     0b000100000111011,
 ];
 
-static VERSION_INFO: [u32; 40] = [
+pub(crate) static VERSION_INFO: [u32; 40] = [
     0,
     0,
     0,
@@ -574,3 +795,106 @@ static VERSION_INFO: [u32; 40] = [
     0b100111010101000001,
     0b101000110001101001,
 ];
+
+#[test]
+fn test_data_modules() {
+    for v in 1..=40 {
+        let version = Version::new(v).unwrap();
+        let mut walk = function_patterns(version);
+        let free = (0..walk.done.size()).flat_map(|y| (0..walk.done.size()).map(move |x| (x, y))).filter(|(x, y)| !walk.done.get(*x, *y)).count();
+
+        // the same order as the cursor walk, and every module that isn't a function pattern exactly once
+        let size = walk.done.size();
+        let mut cursor = (size - 1, size - 1, true, true);
+        let modules = version.data_modules().collect::<Vec<_>>();
+        for (x, y) in &modules {
+            assert_eq!((*x, *y), (cursor.0, cursor.1), "version {v}");
+            place_data(&mut walk, &mut cursor, false);
+        }
+        assert_eq!(modules.len(), free, "version {v}");
+    }
+
+    assert_eq!(Version::new(1).unwrap().data_modules().take(3).collect::<Vec<_>>(), [(20, 20), (19, 20), (20, 19)]);
+    assert_eq!(Version::new(1).unwrap().data_modules().count(), 26 * 8);
+}
+
+#[test]
+fn test_generate_into() {
+    const LEN: usize = QrMatrix::buffer_len(Version::MAX);
+    let mut buffer = [0; LEN];
+    let mut temp = [0; QrMatrix::temp_len(Version::MAX)];
+
+    let segments = [Segment::new(Mode::Alphanumeric, b"HELLO WORLD"), Segment::new(Mode::Bytes, b"bytes")];
+    for (v, ec) in [(1, ErrorCorrectLv::L), (5, ErrorCorrectLv::Q), (7, ErrorCorrectLv::H), (40, ErrorCorrectLv::M)] {
+        let version = Version::new(v).unwrap();
+        let expected = QrMatrix::generate_segments(&segments, version, ec).unwrap();
+        let mat = QrMatrix::generate_into(&segments, version, ec, &mut buffer, &mut temp).unwrap();
+
+        assert_eq!(mat.size(), expected.size());
+        assert!((0..mat.size()).all(|y| (0..mat.size()).all(|x| mat.get(x, y) == expected.get(x, y))), "version {v}");
+    }
+
+    let v1 = Version::new(1).unwrap();
+    assert_eq!(QrMatrix::buffer_len(v1), 63);
+    assert_eq!(
+        QrMatrix::generate_into(&segments, v1, ErrorCorrectLv::L, &mut [0; 62], &mut temp),
+        Err(Error::BufferTooSmall { needed: 63, actual: 62 }),
+    );
+    assert_eq!(
+        QrMatrix::generate_into(&segments, v1, ErrorCorrectLv::L, &mut buffer, &mut [0; 63]),
+        Err(Error::BufferTooSmall { needed: 126 + 21 * 48, actual: 63 }),
+    );
+    assert_eq!(
+        QrMatrix::generate_into(&[Segment::new(Mode::Numeric, &[b'1'; 42])], v1, ErrorCorrectLv::L, &mut buffer, &mut temp).map(|m| m.size()),
+        Err(Error::DataTooLong { needed: 4 + 10 + 140, capacity: 152 }),
+    );
+}
+
+// pinned output since the spec-exact penalty, generation mustn't drift
+#[test]
+fn test_golden() {
+    let mat = QrMatrix::generate(b"HELLO WORLD", Mode::Alphanumeric, Version::new(1).unwrap(), ErrorCorrectLv::Q).unwrap();
+    let expected = [
+        "#######.##....#######",
+        "#.....#.#..#..#.....#",
+        "#.###.#.#..##.#.###.#",
+        "#.###.#.#.....#.###.#",
+        "#.###.#.#.#...#.###.#",
+        "#.....#...#...#.....#",
+        "#######.#.#.#.#######",
+        "........#............",
+        ".##.#.##....#.#.#####",
+        ".#......####....#...#",
+        "..##.###.##...#.##...",
+        ".##.##.#..##.#.#.###.",
+        "#...#.#.#.###.###.#.#",
+        "........##.#..#...#.#",
+        "#######.#.#....#.##..",
+        "#.....#..#.##.##.#...",
+        "#.###.#.#.#...#######",
+        "#.###.#..#.#.#.#...#.",
+        "#.###.#.#..#.###.#..#",
+        "#.....#.#.####...#.##",
+        "#######....#.###....#",
+    ];
+
+    for (y, row) in expected.iter().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            assert_eq!(mat.get(x, y), c == '#', "({x}, {y})");
+        }
+    }
+
+    // and a checksum over every version and level
+    let mut hash = 0_u64;
+    for v in 1..=40 {
+        for ec in [ErrorCorrectLv::L, ErrorCorrectLv::M, ErrorCorrectLv::Q, ErrorCorrectLv::H] {
+            let mat = QrMatrix::generate(b"hi", Mode::Bytes, Version::new(v).unwrap(), ec).unwrap();
+            for y in 0..mat.size() {
+                for x in 0..mat.size() {
+                    hash = hash.wrapping_mul(31).wrapping_add(mat.get(x, y) as u64);
+                }
+            }
+        }
+    }
+    assert_eq!(hash, 0x5e4b4a5b972c78d3);
+}
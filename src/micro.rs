@@ -0,0 +1,244 @@
+use alloc::vec;
+
+use crate::err_corr::generate_ec;
+use crate::matrix::{flip_mask, place_data, place_finder, UnfinishedMatrix};
+use crate::{encode, Error, ErrorCorrectLv, Mode, QrMatrix, Segment};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MicroVersion(u8);
+
+impl MicroVersion {
+    pub const MIN: Self = Self(0);
+    pub const MAX: Self = Self(3);
+
+    pub fn new(v: u8) -> Result<Self, Error> {
+        if !(1..=4).contains(&v) { return Err(Error::MicroVersionOutOfRange(v)) };
+        Ok(Self(v - 1))
+    }
+
+    pub fn version(self) -> u8 {
+        self.0 + 1
+    }
+
+    pub fn size(self) -> usize {
+        self.0 as usize * 2 + 11
+    }
+
+    pub fn smallest_version_for(segments: &[Segment], ec: ErrorCorrectLv) -> Result<Self, Error> {
+        (0..4).map(Self)
+            .find(|v| v.fits(segments, ec).is_ok())
+            .ok_or_else(|| Self::MAX.fits(segments, ec).unwrap_err())
+    }
+
+    pub fn char_count_length(self, mode: Mode) -> Result<usize, Error> {
+        match MICRO_CHAR_COUNT_LENGTHS[self.0 as usize][mode as usize] {
+            0 => Err(Error::UnsupportedMode(mode)),
+            l => Ok(l),
+        }
+    }
+
+    pub fn bit_length(self, segments: &[Segment]) -> Result<usize, Error> {
+        segments.iter()
+            .map(|s| Ok(self.indicator_length() + self.char_count_length(s.mode())? + s.mode().data_bit_length(s.char_count())))
+            .sum()
+    }
+
+    pub(crate) fn fits(self, segments: &[Segment], ec: ErrorCorrectLv) -> Result<(), Error> {
+        let capacity = self.data_bits(ec)?;
+        let needed = self.bit_length(segments)?;
+
+        for s in segments.iter() {
+            if s.char_count() >= 1 << self.char_count_length(s.mode())? {
+                return Err(Error::DataTooLong { needed, capacity });
+            }
+        }
+
+        if needed > capacity { return Err(Error::DataTooLong { needed, capacity }) };
+        Ok(())
+    }
+
+    // the symbol number from the format info, which also indexes MICRO_BYTES
+    fn symbol(self, ec: ErrorCorrectLv) -> Result<usize, Error> {
+        match (self.0, ec) {
+            // m1 only has error detection, L stands in for it
+            (0, ErrorCorrectLv::L) => Ok(0),
+            (1 | 2, ErrorCorrectLv::L | ErrorCorrectLv::M) => Ok(self.0 as usize * 2 - 1 + ec as usize),
+            (3, ErrorCorrectLv::L | ErrorCorrectLv::M | ErrorCorrectLv::Q) => Ok(5 + ec as usize),
+            _ => Err(Error::UnsupportedEcLevel(ec)),
+        }
+    }
+
+    pub(crate) fn data_bits(self, ec: ErrorCorrectLv) -> Result<usize, Error> {
+        Ok(MICRO_BYTES[self.symbol(ec)?][0])
+    }
+
+    // the modes are numbered in declaration order, in as few bits as the version needs
+    pub(crate) fn indicator_length(self) -> usize {
+        self.0 as usize
+    }
+
+    pub(crate) fn terminator_length(self) -> usize {
+        self.0 as usize * 2 + 3
+    }
+}
+
+impl QrMatrix {
+    pub fn generate_micro(string: &[u8], mode: Mode, version: MicroVersion, ec: ErrorCorrectLv) -> Result<Self, Error> {
+        Self::generate_micro_segments(&[Segment::new(mode, string)], version, ec)
+    }
+
+    pub fn generate_micro_segments(segments: &[Segment], version: MicroVersion, ec: ErrorCorrectLv) -> Result<Self, Error> {
+        let data = encode::encode_micro(segments, version, ec)?;
+        let symbol = version.symbol(ec)?;
+        let [data_bits, ec_bytes] = MICRO_BYTES[symbol];
+
+        let mut ecc = vec![0; ec_bytes];
+        generate_ec(&data, &mut ecc);
+
+        let mut mat = function_patterns(version);
+        let mut cursor = (mat.matrix.size() - 1, mat.matrix.size() - 1, true, true);
+        for (i, mut b) in data.iter().chain(ecc.iter()).copied().enumerate() {
+            // m1 and m3 end their data with a 4 bit codeword
+            let bits = if i + 1 == data.len() { data_bits - i * 8 } else { 8 };
+
+            for _ in 0..bits {
+                place_data(&mut mat, &mut cursor, b & 0x80 != 0);
+                b <<= 1;
+            }
+        }
+
+        mat.matrix.mask = Some(apply_best_mask(&mut mat.matrix, &mat.done, symbol));
+        Ok(mat.matrix)
+    }
+}
+
+fn function_patterns(version: MicroVersion) -> UnfinishedMatrix {
+    let size = version.size();
+    let mut mat = UnfinishedMatrix::new(size, size, Some(0));
+
+    place_finder(&mut mat, 0, 0);
+
+    // timing
+    for i in 8..size {
+        mat.set(i, 0, i & 1 == 0);
+        mat.set(0, i, i & 1 == 0);
+    }
+
+    // reserved area
+    mat.set_hline(1, 8, 8, false);
+    mat.set_vline(8, 1, 7, false);
+
+    mat
+}
+
+fn apply_best_mask(mat: &mut QrMatrix, functions: &QrMatrix, symbol: usize) -> usize {
+    let mut best_mask = 0;
+    let mut best_score = 0;
+
+    for (m, mask) in MICRO_MASKS.iter().enumerate() {
+        flip_mask(mat, functions, *mask);
+        let score = calculate_score(mat);
+        flip_mask(mat, functions, *mask);
+
+        if score > best_score {
+            best_mask = m;
+            best_score = score;
+        }
+    }
+
+    flip_mask(mat, functions, MICRO_MASKS[best_mask]);
+    place_format(mat, symbol, best_mask);
+    best_mask
+}
+
+// unlike full qr the best mask scores highest: dark modules along the two edges without timing
+fn calculate_score(mat: &QrMatrix) -> usize {
+    let last = mat.size() - 1;
+    let right = (1..mat.size()).filter(|y| mat.get(last, *y)).count();
+    let bottom = (1..mat.size()).filter(|x| mat.get(*x, last)).count();
+
+    right.min(bottom) * 16 + right.max(bottom)
+}
+
+fn place_format(mat: &mut QrMatrix, symbol: usize, mask: usize) {
+    let format = MICRO_FORMAT_INFO[symbol * 4 + mask];
+
+    for i in 0..15 {
+        let (x, y) = if i < 8 { (i + 1, 8) } else { (8, 15 - i) };
+        mat.set(x, y, (format << i) & 0x4000 != 0);
+    }
+}
+
+// the full qr masks that keep their meaning in a micro symbol
+static MICRO_MASKS: [usize; 4] = [1, 4, 6, 7];
+
+// numeric, alphanumeric, bytes, kanji, zero where the mode doesn't exist
+static MICRO_CHAR_COUNT_LENGTHS: [[usize; 4]; 4] = [
+    [3, 0, 0, 0],
+    [4, 3, 0, 0],
+    [5, 4, 4, 3],
+    [6, 5, 5, 4],
+];
+
+//  Data bits | EC bytes, for M1, M2-L, M2-M, M3-L, M3-M, M4-L, M4-M and M4-Q
+static MICRO_BYTES: [[usize; 2]; 8] = [
+    [20, 2],
+    [40, 5],
+    [32, 6],
+    [84, 6],
+    [68, 8],
+    [128, 8],
+    [112, 10],
+    [80, 14],
+];
+
+// symbol number and mask, bch(15, 5) and xored with 0x4445
+static MICRO_FORMAT_INFO: [u16; 8 * 4] = [
+    0x4445, 0x4172, 0x4e2b, 0x4b1c,
+    0x55ae, 0x5099, 0x5fc0, 0x5af7,
+    0x6793, 0x62a4, 0x6dfd, 0x68ca,
+    0x7678, 0x734f, 0x7c16, 0x7921,
+    0x06de, 0x03e9, 0x0cb0, 0x0987,
+    0x1735, 0x1202, 0x1d5b, 0x186c,
+    0x2508, 0x203f, 0x2f66, 0x2a51,
+    0x34e3, 0x31d4, 0x3e8d, 0x3bba,
+];
+
+#[test]
+fn test_micro() {
+    let mat = QrMatrix::generate_micro(b"01234", Mode::Numeric, MicroVersion::new(1).unwrap(), ErrorCorrectLv::L).unwrap();
+    let expected = [
+        "#######.#.#",
+        "#.....#...#",
+        "#.###.#.###",
+        "#.###.#..#.",
+        "#.###.#....",
+        "#.....#.#.#",
+        "#######.#.#",
+        "........###",
+        "##.....#...",
+        "....###.#.#",
+        "###.#.####.",
+    ];
+
+    assert_eq!(mat.size(), 11);
+    for (y, row) in expected.iter().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            assert_eq!(mat.get(x, y), c == '#', "({x}, {y})");
+        }
+    }
+
+    let m2 = MicroVersion::new(2).unwrap();
+    assert_eq!(QrMatrix::generate_micro(b"HELLO", Mode::Alphanumeric, m2, ErrorCorrectLv::L).map(|m| m.size()), Ok(13));
+    assert_eq!(QrMatrix::generate_micro(b"hi", Mode::Bytes, m2, ErrorCorrectLv::L), Err(Error::UnsupportedMode(Mode::Bytes)));
+    assert_eq!(QrMatrix::generate_micro(b"1", Mode::Numeric, m2, ErrorCorrectLv::Q), Err(Error::UnsupportedEcLevel(ErrorCorrectLv::Q)));
+    assert_eq!(MicroVersion::new(5), Err(Error::MicroVersionOutOfRange(5)));
+
+    let segments = [Segment::new(Mode::Numeric, b"123456")];
+    assert_eq!(MicroVersion::smallest_version_for(&segments, ErrorCorrectLv::L), MicroVersion::new(2));
+    assert_eq!(MicroVersion::smallest_version_for(&segments, ErrorCorrectLv::Q), MicroVersion::new(4));
+    assert_eq!(
+        MicroVersion::smallest_version_for(&[Segment::new(Mode::Bytes, &[0; 10])], ErrorCorrectLv::Q),
+        Err(Error::DataTooLong { needed: 3 + 5 + 80, capacity: 80 }),
+    );
+}
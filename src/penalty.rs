@@ -0,0 +1,457 @@
+use crate::matrix::{format_and_version, module, MASK_ROWS};
+use crate::{ErrorCorrectLv, QrMatrix, Version};
+
+// the four mask evaluation rules of iso 18004 7.8.3, const so generate_const scores the same way
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Penalty {
+    pub(crate) n1: usize,
+    pub(crate) n2: usize,
+    pub(crate) n3: usize,
+    pub(crate) n4: usize,
+}
+
+impl Penalty {
+    // runs of 5 or more same colored modules in a row or column
+    pub const fn n1(&self) -> usize { self.n1 }
+
+    // 2x2 blocks of one color
+    pub const fn n2(&self) -> usize { self.n2 }
+
+    // 1:1:3:1:1 finder lookalikes with light 4 times their scale on a side
+    pub const fn n3(&self) -> usize { self.n3 }
+
+    // dark modules straying from half
+    pub const fn n4(&self) -> usize { self.n4 }
+
+    pub const fn total(&self) -> usize {
+        self.n1 + self.n2 + self.n3 + self.n4
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PenaltyReport {
+    pub(crate) penalties: [Penalty; 8],
+    pub(crate) mask: usize,
+}
+
+impl PenaltyReport {
+    pub fn penalties(&self) -> &[Penalty; 8] { &self.penalties }
+
+    // the mask the symbol actually uses
+    pub fn mask(&self) -> usize { self.mask }
+
+    // the mask the spec would pick, the lowest one on ties
+    pub fn best_mask(&self) -> usize {
+        (0..8).min_by_key(|m| self.penalties[*m].total()).unwrap()
+    }
+}
+
+pub(crate) const fn calculate_penalty(bitmap: &[u8], stride: usize, size: usize) -> Penalty {
+    let mut penalty = Penalty { n1: 0, n2: 0, n3: 0, n4: 0 };
+    let mut dark = 0;
+
+    // rows, then columns
+    let mut pass = 0;
+    while pass < 2 {
+        let row = pass == 0;
+        let mut j = 0;
+        while j < size {
+            // every line starts a fresh run, light like the quiet zone before it
+            let mut runs = [0; 7];
+            let mut color = false;
+            let mut run = 0;
+            let mut i = 0;
+            while i < size {
+                let c = line_module(bitmap, stride, row, i, j);
+                dark += (row && c) as usize;
+
+                if c == color {
+                    run += 1;
+                    if run == 5 {
+                        penalty.n1 += 3;
+                    } else if run > 5 {
+                        penalty.n1 += 1;
+                    }
+                } else {
+                    push_run(&mut runs, run, size);
+                    if !color { penalty.n3 += finder_like(&runs) * 40 };
+                    color = c;
+                    run = 1;
+                }
+                i += 1;
+            }
+
+            // and ends in the quiet zone after it
+            if color {
+                push_run(&mut runs, run, size);
+                run = 0;
+            }
+            push_run(&mut runs, run + size, size);
+            penalty.n3 += finder_like(&runs) * 40;
+
+            j += 1;
+        }
+        pass += 1;
+    }
+
+    let mut y = 0;
+    while y + 1 < size {
+        let mut x = 0;
+        while x + 1 < size {
+            let c = module(bitmap, stride, x, y);
+            if c == module(bitmap, stride, x + 1, y) && c == module(bitmap, stride, x, y + 1) && c == module(bitmap, stride, x + 1, y + 1) {
+                penalty.n2 += 3;
+            }
+            x += 1;
+        }
+        y += 1;
+    }
+
+    penalty.n4 = balance(dark, size);
+    penalty
+}
+
+// 10 for every full 5% step away from 50% dark, either way
+const fn balance(dark: usize, size: usize) -> usize {
+    let total = size * size;
+    (dark * 20).abs_diff(total * 10) / total * 10
+}
+
+// newest run first, the first run of a line gets the quiet zone added on
+const fn push_run(runs: &mut [usize; 7], mut len: usize, size: usize) {
+    if runs[0] == 0 { len += size };
+
+    let mut i = 6;
+    while i > 0 {
+        runs[i] = runs[i - 1];
+        i -= 1;
+    }
+    runs[0] = len;
+}
+
+// 1:1:3:1:1 at any scale ending in the light run just pushed, counted once for 4 times the scale of light
+// before it and once for as much after, so a pattern with both counts twice
+const fn finder_like(runs: &[usize; 7]) -> usize {
+    let n = runs[1];
+    let core = n > 0 && runs[2] == n && runs[3] == n * 3 && runs[4] == n && runs[5] == n;
+    (core && runs[6] >= n * 4 && runs[0] >= n) as usize + (core && runs[0] >= n * 4 && runs[6] >= n) as usize
+}
+
+// module i along row j, or down column j
+const fn line_module(bitmap: &[u8], stride: usize, row: bool, i: usize, j: usize) -> bool {
+    if row { module(bitmap, stride, i, j) } else { module(bitmap, stride, j, i) }
+}
+
+// the runtime scores from rows packed into words instead, wide enough for version 40, and has to
+// land on exactly what calculate_penalty says
+pub(crate) const WORDS: usize = 3;
+pub(crate) type Row = [u64; WORDS];
+
+// the packed rows live in scratch the caller lends as little endian words, a mask's rows and then
+// their transpose, rather than in arrays that would put 17k on the stack at version 40
+const ROW_BYTES: usize = WORDS * 8;
+
+pub(crate) const fn scratch_len(size: usize) -> usize {
+    size * ROW_BYTES * 2
+}
+
+// masks outside the range are left at zero
+pub(crate) fn score_masks(mat: &QrMatrix<impl AsRef<[u8]>>, functions: &QrMatrix<impl AsRef<[u8]>>, version: Version, ec: ErrorCorrectLv, masks: core::ops::Range<usize>, scratch: &mut [u8]) -> [Penalty; 8] {
+    let (data, functions, size) = (mat.bitmap.as_ref(), functions.bitmap.as_ref(), mat.size());
+    let mut penalties = [Penalty::default(); 8];
+
+    // a thread per mask only pays for itself on the bigger symbols, and each needs scratch of its own
+    #[cfg(feature = "threaded")]
+    if version.version() >= THREADED_VERSION {
+        std::thread::scope(|s| {
+            let threads = masks.clone()
+                .map(|m| (m, s.spawn(move || packed_penalty(data, functions, size, version, ec, m, &mut alloc::vec![0; scratch_len(size)]))))
+                .collect::<alloc::vec::Vec<_>>();
+            for (m, thread) in threads {
+                penalties[m] = thread.join().unwrap();
+            }
+        });
+        return penalties;
+    }
+
+    for m in masks {
+        penalties[m] = packed_penalty(data, functions, size, version, ec, m, scratch);
+    }
+    penalties
+}
+
+#[cfg(feature = "threaded")]
+const THREADED_VERSION: u8 = 25;
+
+fn packed_penalty(data: &[u8], functions: &[u8], size: usize, version: Version, ec: ErrorCorrectLv, mask: usize, scratch: &mut [u8]) -> Penalty {
+    let (rows, columns) = scratch[..scratch_len(size)].split_at_mut(size * ROW_BYTES);
+    let (inside, stride) = (line_mask(size), size.div_ceil(8));
+    for (y, row) in rows.chunks_exact_mut(ROW_BYTES).enumerate() {
+        let (data, functions) = (pack(data, y * stride), pack(functions, y * stride));
+        store(row, core::array::from_fn(|k| (data[k] ^ MASK_ROWS[mask][y % 12][k] & !functions[k]) & inside[k]));
+    }
+    for (x, y, v) in format_and_version(size, version, ec, mask) {
+        let row = &mut rows[y * ROW_BYTES..][..ROW_BYTES];
+        let mut bits = load(row);
+        bits[x / 64] = bits[x / 64] & !(1 << (x % 64)) | (v as u64) << (x % 64);
+        store(row, bits);
+    }
+
+    rows_penalty(rows, columns, size)
+}
+
+// the line of bitmap starting at a byte, which are already in the order the words want them, with
+// whatever comes after it left for the caller to mask off
+fn pack(bitmap: &[u8], at: usize) -> Row {
+    match bitmap.get(at..at + ROW_BYTES) {
+        Some(bytes) => load(bytes),
+        None => {
+            let mut row = [0; ROW_BYTES];
+            row[..bitmap.len() - at].copy_from_slice(&bitmap[at..]);
+            load(&row)
+        },
+    }
+}
+
+fn load(bytes: &[u8]) -> Row {
+    let bytes: &[u8; ROW_BYTES] = bytes.try_into().unwrap();
+    core::array::from_fn(|k| u64::from_le_bytes(bytes[k * 8..][..8].try_into().unwrap()))
+}
+
+fn store(bytes: &mut [u8], row: Row) {
+    for (chunk, word) in bytes.chunks_exact_mut(8).zip(row) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+}
+
+// bits past the end of a line are clear, and shifts bring in clear bits, so the quiet zone is light here too
+fn rows_penalty(rows: &[u8], columns: &mut [u8], size: usize) -> Penalty {
+    let mut penalty = Penalty::default();
+    transpose(rows, columns, size);
+    for lines in [rows, &*columns] {
+        for row in lines.chunks_exact(ROW_BYTES) {
+            line_penalty(&load(row), size, &mut penalty);
+        }
+    }
+
+    // a 2x2 block starts at every x where both rows match their right neighbour and each other
+    let left = line_mask(size - 1);
+    for (a, b) in rows.chunks_exact(ROW_BYTES).zip(rows.chunks_exact(ROW_BYTES).skip(1)) {
+        let (a, b) = (&load(a), &load(b));
+        let same: Row = core::array::from_fn(|k| !(a[k] ^ b[k]));
+        let (a_next, same_next) = (down(a, 1), down(&same, 1));
+        penalty.n2 += (0..WORDS).map(|k| (same[k] & same_next[k] & !(a[k] ^ a_next[k]) & left[k]).count_ones() as usize * 3).sum::<usize>();
+    }
+
+    let dark = rows.chunks_exact(8).map(|w| u64::from_le_bytes(w.try_into().unwrap()).count_ones() as usize).sum();
+    penalty.n4 = balance(dark, size);
+    penalty
+}
+
+fn line_penalty(row: &Row, size: usize, penalty: &mut Penalty) {
+    let inside = line_mask(size);
+    let count = |bits: Row| bits.iter().map(|w| w.count_ones() as usize).sum::<usize>();
+
+    // bit x of five is set when modules x - 4 to x match, which happens len - 4 times in a run, and
+    // each run gets 2 more on top
+    let same: Row = core::array::from_fn(|k| !(row[k] ^ up(row, 1)[k]) & inside[k] & if k == 0 { !1 } else { !0 });
+    let five: Row = core::array::from_fn(|k| same[k] & up(&same, 1)[k] & up(&same, 2)[k] & up(&same, 3)[k]);
+    penalty.n1 += count(five) + 2 * count(core::array::from_fn(|k| five[k] & !up(&five, 1)[k]));
+
+    // 1011101 starting at x, bounded by light on both sides and with 4 of it on either
+    let at = |i: isize| if i < 0 { up(row, -i as u32) } else { down(row, i as u32) };
+    let finder: Row = core::array::from_fn(|k| row[k] & !at(1)[k] & at(2)[k] & at(3)[k] & at(4)[k] & !at(5)[k] & at(6)[k] & !at(-1)[k] & !at(7)[k]);
+    let before: Row = core::array::from_fn(|k| finder[k] & !(at(-2)[k] | at(-3)[k] | at(-4)[k]));
+    let after: Row = core::array::from_fn(|k| finder[k] & !(at(8)[k] | at(9)[k] | at(10)[k]));
+    penalty.n3 += (count(before) + count(after)) * 40;
+
+    // the same pattern scaled up has a dark run of 6 or more in the middle, which is rare enough to
+    // measure the runs around one module at a time
+    let six: Row = core::array::from_fn(|k| row[k] & !at(-1)[k] & at(1)[k] & at(2)[k] & at(3)[k] & at(4)[k] & at(5)[k]);
+    for (k, mut word) in six.into_iter().enumerate() {
+        while word != 0 {
+            penalty.n3 += scaled_finder(row, size, (k * 64 + word.trailing_zeros() as usize) as isize) * 40;
+            word &= word - 1;
+        }
+    }
+}
+
+// the runs either side of a dark run starting at x, counted like finder_like counts its history
+fn scaled_finder(row: &Row, size: usize, x: isize) -> usize {
+    let run = |from: isize, step: isize| {
+        let dark = module_at(row, size, from);
+        let mut i = from;
+        while (0..size as isize).contains(&i) && module_at(row, size, i) == dark {
+            i += step;
+        }
+
+        // a light run off the edge goes on into the quiet zone
+        (i - from).unsigned_abs() + if dark || (0..size as isize).contains(&i) { 0 } else { size }
+    };
+
+    let center = run(x, 1);
+    if center % 3 != 0 { return 0 };
+    let n = center / 3;
+    let (left, right) = (x - 1, x + center as isize);
+    let n = n as isize;
+
+    let core = run(left, -1) == n as usize && run(left - n, -1) == n as usize
+        && run(right, 1) == n as usize && run(right + n, 1) == n as usize;
+    if !core { return 0 };
+
+    let (before, after) = (run(left - 2 * n, -1), run(right + 2 * n, 1));
+    let n = n as usize;
+    (before >= n * 4 && after >= n) as usize + (after >= n * 4 && before >= n) as usize
+}
+
+fn module_at(row: &Row, size: usize, i: isize) -> bool {
+    (0..size as isize).contains(&i) && row[i as usize / 64] >> (i % 64) & 1 != 0
+}
+
+fn line_mask(len: usize) -> Row {
+    core::array::from_fn(|k| match len.saturating_sub(k * 64) {
+        0 => 0,
+        n @ 1..64 => (1 << n) - 1,
+        _ => !0,
+    })
+}
+
+// bit x of the result is bit x - n of the row
+fn up(row: &Row, n: u32) -> Row {
+    core::array::from_fn(|k| row[k] << n | if k > 0 { row[k - 1] >> (64 - n) } else { 0 })
+}
+
+// bit x of the result is bit x + n of the row
+fn down(row: &Row, n: u32) -> Row {
+    core::array::from_fn(|k| row[k] >> n | if k + 1 < WORDS { row[k + 1] << (64 - n) } else { 0 })
+}
+
+// 64x64 blocks at a time, each transposed in place by swapping ever smaller quarters
+fn transpose(rows: &[u8], columns: &mut [u8], size: usize) {
+    // only the words that reach into the symbol get written
+    columns.fill(0);
+
+    let bands = size.div_ceil(64);
+    for by in 0..bands {
+        for bx in 0..bands {
+            let mut block = [0; 64];
+            for (w, row) in block.iter_mut().zip(rows.chunks_exact(ROW_BYTES).skip(by * 64)) {
+                *w = u64::from_le_bytes(row[bx * 8..][..8].try_into().unwrap());
+            }
+
+            let mut j = 32;
+            let mut m = 0x0000_0000_ffff_ffff_u64;
+            while j != 0 {
+                let mut k = 0;
+                while k < 64 {
+                    let t = (block[k] >> j ^ block[k + j]) & m;
+                    block[k] ^= t << j;
+                    block[k + j] ^= t;
+                    k = (k + j + 1) & !j;
+                }
+                j >>= 1;
+                m ^= m << j;
+            }
+
+            for (column, w) in columns.chunks_exact_mut(ROW_BYTES).skip(bx * 64).zip(block) {
+                column[by * 8..][..8].copy_from_slice(&w.to_le_bytes());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_penalty() {
+    use alloc::vec;
+
+    let bitmap = |rows: &[&str]| {
+        let stride = rows.len().div_ceil(8);
+        let mut bitmap = vec![0; stride * rows.len()];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                bitmap[x / 8 + y * stride] |= ((c == '#') as u8) << (x % 8);
+            }
+        }
+        (bitmap, stride)
+    };
+    let penalty = |rows: &[&str]| {
+        let (bitmap, stride) = bitmap(rows);
+        calculate_penalty(&bitmap, stride, rows.len())
+    };
+
+    // the light run at the end of the first row doesn't carry into the second
+    let mut rows = [".#.#.#.#", "#.#.#.#."].repeat(4);
+    rows[0] = "#####...";
+    rows[1] = "...#####";
+    assert_eq!(penalty(&rows).n1(), 6);
+
+    // light on both sides counts twice, past the edge is light, and the ratio holds at any scale
+    let mut rows = vec!["..........."; 11];
+    rows[0] = "#.###.#....";
+    assert_eq!(penalty(&rows).n3(), 80);
+    rows[0] = "#.###.#..#.";
+    assert_eq!(penalty(&rows).n3(), 40);
+    rows[0] = "...#.###.##";
+    assert_eq!(penalty(&rows).n3(), 0);
+    let mut rows = vec!["..............."; 15];
+    rows[0] = "##..######..##.";
+    assert_eq!(penalty(&rows).n3(), 80);
+
+    // 36% and 64% are both two whole steps from half
+    assert_eq!(penalty(&["#####", "####.", ".....", ".....", "....."]).n4(), 20);
+    assert_eq!(penalty(&["#####", "#####", "#####", "#....", "....."]).n4(), 20);
+    assert_eq!(penalty(&["#####", "#####", "#....", ".....", "....."]).n4(), 10);
+
+    let mat = crate::QrMatrix::generate(b"HELLO WORLD", crate::Mode::Alphanumeric, crate::Version::MIN, crate::ErrorCorrectLv::Q).unwrap();
+    let report = mat.penalty_report().unwrap();
+    assert_eq!((Some(report.mask()), Some(report.best_mask())), (mat.mask(), mat.mask()));
+    assert_eq!(Some(report.penalties()[report.mask()]), mat.penalty());
+    assert!(report.penalties().iter().all(|p| p.total() >= mat.penalty().unwrap().total()));
+}
+
+#[test]
+fn test_packed_penalty() {
+    use crate::{Mode, QrBuilder, Segment};
+
+    // every mask of every version, scored by the packed path while generating and by the reference after
+    for v in 1..=40 {
+        for m in 0..8 {
+            let ec = [ErrorCorrectLv::L, ErrorCorrectLv::M, ErrorCorrectLv::Q, ErrorCorrectLv::H][(v + m) % 4];
+            let mat = QrBuilder::new(ec)
+                .segment(Segment::new(Mode::Bytes, &[(v * 37 + m) as u8; 7]))
+                .version(Version::new(v as u8).unwrap())
+                .mask(m)
+                .build()
+                .unwrap();
+
+            assert_eq!(mat.penalty(), Some(calculate_penalty(&mat.bitmap, mat.stride, mat.size())), "version {v} mask {m}");
+        }
+    }
+
+    // noise at a few scales, so the scaled finder patterns come up too
+    for (size, scale) in [(21_usize, 1), (64, 2), (65, 3), (129, 4), (177, 5)] {
+        let dark = |x: usize, y: usize| ((x / scale * 31 + y / scale) as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 61 & 1 != 0;
+        let stride = size.div_ceil(8);
+        let mut bitmap = alloc::vec![0; stride * size];
+        let mut scratch = alloc::vec![0xa5; scratch_len(size)];
+        let (rows, columns) = scratch.split_at_mut(size * ROW_BYTES);
+        rows.fill(0);
+        for (x, y) in (0..size).flat_map(|y| (0..size).map(move |x| (x, y))).filter(|(x, y)| dark(*x, *y)) {
+            bitmap[x / 8 + y * stride] |= 1 << (x % 8);
+            rows[y * ROW_BYTES + x / 8] |= 1 << (x % 8);
+        }
+
+        // whatever the scratch held before doesn't leak into the columns
+        assert_eq!(rows_penalty(rows, columns, size), calculate_penalty(&bitmap, stride, size), "size {size}");
+    }
+
+    let size = 177;
+    let mut scratch = alloc::vec![0; scratch_len(size)];
+    let (rows, columns) = scratch.split_at_mut(size * ROW_BYTES);
+    for (y, row) in rows.chunks_exact_mut(ROW_BYTES).enumerate() {
+        store(row, core::array::from_fn(|k| (y as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15).rotate_left(k as u32 * 21) & line_mask(size)[k]));
+    }
+    transpose(rows, columns, size);
+    let bit = |lines: &[u8], i: usize, j: usize| lines[i * ROW_BYTES + j / 8] >> (j % 8) & 1;
+    assert!((0..size).all(|y| (0..size).all(|x| bit(rows, y, x) == bit(columns, x, y))));
+}
@@ -0,0 +1,107 @@
+mod png;
+mod raster;
+mod svg;
+mod text;
+
+pub use png::Png;
+pub use raster::{Luma, Pbm, Pgm, Rgba};
+pub use svg::Svg;
+pub use text::{Ansi, HalfBlock};
+
+use crate::QrMatrix;
+
+pub trait Renderer {
+    type Output;
+
+    // quiet zone in modules, and pixels (or characters) per module
+    fn layout(&self) -> (usize, usize);
+
+    fn draw(&self, canvas: &Canvas) -> Self::Output;
+
+    fn render(&self, mat: &QrMatrix) -> Self::Output {
+        let (quiet_zone, scale) = self.layout();
+        self.draw(&Canvas { mat, quiet_zone, scale: scale.max(1) })
+    }
+}
+
+// the symbol with its quiet zone around it, addressable per module or per scaled pixel
+#[derive(Debug, Clone, Copy)]
+pub struct Canvas<'a> {
+    mat: &'a QrMatrix,
+    quiet_zone: usize,
+    scale: usize,
+}
+
+// widths come before heights everywhere, they only differ for rmqr
+impl Canvas<'_> {
+    pub fn modules(&self) -> (usize, usize) {
+        (self.mat.width() + self.quiet_zone * 2, self.mat.height() + self.quiet_zone * 2)
+    }
+
+    pub fn module(&self, x: usize, y: usize) -> bool {
+        let (x, y) = (x.wrapping_sub(self.quiet_zone), y.wrapping_sub(self.quiet_zone));
+        x < self.mat.width() && y < self.mat.height() && self.mat.get(x, y)
+    }
+
+    pub fn scale(&self) -> usize { self.scale }
+
+    pub fn size(&self) -> (usize, usize) {
+        let (width, height) = self.modules();
+        (width * self.scale, height * self.scale)
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.module(x / self.scale, y / self.scale)
+    }
+
+    // one row of pixels at one bit each, most significant first, the way pbm and png both want it
+    fn packed_row(&self, y: usize, row: &mut [u8]) {
+        for x in 0..self.size().0 {
+            if self.pixel(x, y) {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+}
+
+impl QrMatrix {
+    pub fn render<R: Renderer>(&self, renderer: &R) -> R::Output {
+        renderer.render(self)
+    }
+}
+
+#[test]
+fn test_canvas() {
+    use crate::{ErrorCorrectLv, Mode, Version};
+
+    let mat = QrMatrix::generate(b"HELLO WORLD", Mode::Alphanumeric, Version::new(1).unwrap(), ErrorCorrectLv::Q).unwrap();
+    let canvas = Canvas { mat: &mat, quiet_zone: 2, scale: 3 };
+
+    assert_eq!(canvas.modules(), (25, 25));
+    assert_eq!(canvas.size(), (75, 75));
+    assert!(!canvas.module(1, 1) && canvas.module(2, 2) && !canvas.module(24, 24));
+    assert!(canvas.pixel(6, 6) && !canvas.pixel(5, 6));
+
+    let mut row = [0; 10];
+    canvas.packed_row(6, &mut row);
+    assert_eq!(row[..3], [0b00000011, 0xff, 0xff]);
+    assert_eq!(row[3] & 0b11111100, 0b11100000);
+
+    let luma = mat.render(&Luma::new().quiet_zone(2).module_size(3).colors(1, 0));
+    let pbm = mat.render(&Pbm::new().quiet_zone(2).module_size(3));
+    let pgm = mat.render(&Pgm::new().quiet_zone(2).module_size(3));
+    let rgba = mat.render(&Rgba::new().quiet_zone(2).module_size(3));
+
+    assert_eq!(&pbm[..9], b"P4\n75 75\n");
+    assert_eq!(&pgm[..13], b"P5\n75 75\n255\n");
+    for y in 0..75 {
+        for x in 0..75 {
+            let dark = canvas.pixel(x, y);
+
+            assert_eq!(luma[x + y * 75], dark as u8);
+            assert_eq!(pbm[9 + y * 10 + x / 8] & (0x80 >> (x % 8)) != 0, dark);
+            assert_eq!(pgm[13 + x + y * 75], if dark { 0 } else { 255 });
+            assert_eq!(rgba[(x + y * 75) * 4..][..4], if dark { [0, 0, 0, 255] } else { [255; 4] });
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use alloc::{vec, vec::Vec};
+
+use super::{Canvas, Renderer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Png {
+    module_size: usize,
+    quiet_zone: usize,
+    dark: [u8; 3],
+    light: [u8; 3],
+}
+
+impl Default for Png {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Png {
+    pub fn new() -> Self {
+        Self {
+            module_size: 8,
+            quiet_zone: 4,
+            dark: [0, 0, 0],
+            light: [255, 255, 255],
+        }
+    }
+
+    pub fn module_size(mut self, size: usize) -> Self {
+        self.module_size = size;
+        self
+    }
+
+    pub fn quiet_zone(mut self, modules: usize) -> Self {
+        self.quiet_zone = modules;
+        self
+    }
+
+    pub fn colors(mut self, dark: [u8; 3], light: [u8; 3]) -> Self {
+        self.dark = dark;
+        self.light = light;
+        self
+    }
+}
+
+impl Renderer for Png {
+    type Output = Vec<u8>;
+
+    fn layout(&self) -> (usize, usize) {
+        (self.quiet_zone, self.module_size)
+    }
+
+    // a 1-bit palette image, index 0 light and 1 dark
+    fn draw(&self, canvas: &Canvas) -> Vec<u8> {
+        let (width, height) = canvas.size();
+        let stride = width.div_ceil(8);
+
+        // every line starts with filter type 0, no filtering
+        let mut pixels = vec![0; (stride + 1) * height];
+        for (y, line) in pixels.chunks_mut(stride + 1).enumerate() {
+            canvas.packed_row(y, &mut line[1..]);
+        }
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend((width as u32).to_be_bytes());
+        ihdr.extend((height as u32).to_be_bytes());
+        // bit depth 1, palette, deflate, no filter, no interlace
+        ihdr.extend([1, 3, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        chunk(&mut png, b"IHDR", &ihdr);
+        chunk(&mut png, b"PLTE", &[self.light, self.dark].concat());
+        chunk(&mut png, b"IDAT", &zlib_stored(&pixels));
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    png.extend(crc32(&[kind, data]).to_be_bytes());
+}
+
+// stored blocks skip compression entirely, the 1-bit pixels already keep the image small
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut zlib = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend([1, 0, 0, 0xff, 0xff]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend(len.to_le_bytes());
+        zlib.extend((!len).to_le_bytes());
+        zlib.extend(block);
+    }
+
+    zlib.extend(adler32(data).to_be_bytes());
+    zlib
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for d in data.iter() {
+        a = (a + *d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+static CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+
+        table[n] = c;
+        n += 1;
+    }
+
+    table
+}
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    !parts.iter()
+        .flat_map(|p| p.iter())
+        .fold(!0, |c, b| CRC_TABLE[((c ^ *b as u32) & 0xff) as usize] ^ (c >> 8))
+}
+
+#[test]
+fn test_png() {
+    use crate::{ErrorCorrectLv, Mode, QrMatrix, Version};
+
+    assert_eq!(crc32(&[b"IEND"]), 0xae426082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+
+    let mat = QrMatrix::generate(b"HELLO WORLD", Mode::Alphanumeric, Version::new(1).unwrap(), ErrorCorrectLv::Q).unwrap();
+    let png = Png::new().module_size(3).quiet_zone(1).colors([0, 0, 128], [255, 255, 255]).render(&mat);
+
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[8..29], &[0, 0, 0, 13, b'I', b'H', b'D', b'R', 0, 0, 0, 69, 0, 0, 0, 69, 1, 3, 0, 0, 0]);
+    assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]);
+
+    // one stored block: zlib header, block header, then the raw lines
+    let idat = &png[33 + 18 + 8..];
+    let stride = 69_usize.div_ceil(8) + 1;
+    let len = (stride * 69) as u16;
+    assert_eq!(&idat[..7], &[0x78, 0x01, 1, len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+
+    let lines = &idat[7..7 + stride * 69];
+    for y in 0..69 {
+        for x in 0..69 {
+            let dark = lines[y * stride + 1 + x / 8] & (0x80 >> (x % 8)) != 0;
+            let module = (1..22).contains(&(x / 3)) && (1..22).contains(&(y / 3)) && mat.get(x / 3 - 1, y / 3 - 1);
+            assert_eq!(dark, module);
+        }
+    }
+}
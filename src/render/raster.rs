@@ -0,0 +1,215 @@
+use alloc::{format, vec::Vec};
+
+use super::{Canvas, Renderer};
+
+// one byte per pixel, row by row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Luma {
+    module_size: usize,
+    quiet_zone: usize,
+    dark: u8,
+    light: u8,
+}
+
+impl Default for Luma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Luma {
+    pub fn new() -> Self {
+        Self {
+            module_size: 1,
+            quiet_zone: 4,
+            dark: 0,
+            light: 255,
+        }
+    }
+
+    pub fn module_size(mut self, size: usize) -> Self {
+        self.module_size = size;
+        self
+    }
+
+    pub fn quiet_zone(mut self, modules: usize) -> Self {
+        self.quiet_zone = modules;
+        self
+    }
+
+    pub fn colors(mut self, dark: u8, light: u8) -> Self {
+        self.dark = dark;
+        self.light = light;
+        self
+    }
+}
+
+impl Renderer for Luma {
+    type Output = Vec<u8>;
+
+    fn layout(&self) -> (usize, usize) {
+        (self.quiet_zone, self.module_size)
+    }
+
+    fn draw(&self, canvas: &Canvas) -> Vec<u8> {
+        let (width, height) = canvas.size();
+
+        (0..width * height)
+            .map(|i| if canvas.pixel(i % width, i / width) { self.dark } else { self.light })
+            .collect()
+    }
+}
+
+// four bytes per pixel, row by row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgba {
+    module_size: usize,
+    quiet_zone: usize,
+    dark: [u8; 4],
+    light: [u8; 4],
+}
+
+impl Default for Rgba {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rgba {
+    pub fn new() -> Self {
+        Self {
+            module_size: 1,
+            quiet_zone: 4,
+            dark: [0, 0, 0, 255],
+            light: [255, 255, 255, 255],
+        }
+    }
+
+    pub fn module_size(mut self, size: usize) -> Self {
+        self.module_size = size;
+        self
+    }
+
+    pub fn quiet_zone(mut self, modules: usize) -> Self {
+        self.quiet_zone = modules;
+        self
+    }
+
+    pub fn colors(mut self, dark: [u8; 4], light: [u8; 4]) -> Self {
+        self.dark = dark;
+        self.light = light;
+        self
+    }
+}
+
+impl Renderer for Rgba {
+    type Output = Vec<u8>;
+
+    fn layout(&self) -> (usize, usize) {
+        (self.quiet_zone, self.module_size)
+    }
+
+    fn draw(&self, canvas: &Canvas) -> Vec<u8> {
+        let (width, height) = canvas.size();
+
+        (0..width * height)
+            .flat_map(|i| if canvas.pixel(i % width, i / width) { self.dark } else { self.light })
+            .collect()
+    }
+}
+
+// binary (P4) portable bitmap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pbm {
+    module_size: usize,
+    quiet_zone: usize,
+}
+
+impl Default for Pbm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pbm {
+    pub fn new() -> Self {
+        Self { module_size: 1, quiet_zone: 4 }
+    }
+
+    pub fn module_size(mut self, size: usize) -> Self {
+        self.module_size = size;
+        self
+    }
+
+    pub fn quiet_zone(mut self, modules: usize) -> Self {
+        self.quiet_zone = modules;
+        self
+    }
+}
+
+impl Renderer for Pbm {
+    type Output = Vec<u8>;
+
+    fn layout(&self) -> (usize, usize) {
+        (self.quiet_zone, self.module_size)
+    }
+
+    fn draw(&self, canvas: &Canvas) -> Vec<u8> {
+        let (width, height) = canvas.size();
+        let stride = width.div_ceil(8);
+
+        let mut pbm = format!("P4\n{width} {height}\n").into_bytes();
+        let header = pbm.len();
+        pbm.resize(header + stride * height, 0);
+
+        for (y, row) in pbm[header..].chunks_mut(stride).enumerate() {
+            canvas.packed_row(y, row);
+        }
+
+        pbm
+    }
+}
+
+// binary (P5) portable graymap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pgm(Luma);
+
+impl Default for Pgm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pgm {
+    pub fn new() -> Self {
+        Self(Luma::new())
+    }
+
+    pub fn module_size(self, size: usize) -> Self {
+        Self(self.0.module_size(size))
+    }
+
+    pub fn quiet_zone(self, modules: usize) -> Self {
+        Self(self.0.quiet_zone(modules))
+    }
+
+    pub fn colors(self, dark: u8, light: u8) -> Self {
+        Self(self.0.colors(dark, light))
+    }
+}
+
+impl Renderer for Pgm {
+    type Output = Vec<u8>;
+
+    fn layout(&self) -> (usize, usize) {
+        self.0.layout()
+    }
+
+    fn draw(&self, canvas: &Canvas) -> Vec<u8> {
+        let (width, height) = canvas.size();
+
+        let mut pgm = format!("P5\n{width} {height}\n255\n").into_bytes();
+        pgm.extend(self.0.draw(canvas));
+        pgm
+    }
+}
@@ -0,0 +1,134 @@
+use alloc::string::String;
+use core::fmt::Write;
+
+use super::{Canvas, Renderer};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Svg {
+    module_size: usize,
+    quiet_zone: usize,
+    dark: String,
+    light: String,
+    scalable: bool,
+}
+
+impl Default for Svg {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Svg {
+    pub fn new() -> Self {
+        Self {
+            module_size: 8,
+            quiet_zone: 4,
+            dark: "#000".into(),
+            light: "#fff".into(),
+            scalable: false,
+        }
+    }
+
+    pub fn module_size(mut self, size: usize) -> Self {
+        self.module_size = size;
+        self
+    }
+
+    pub fn quiet_zone(mut self, modules: usize) -> Self {
+        self.quiet_zone = modules;
+        self
+    }
+
+    pub fn colors(mut self, dark: &str, light: &str) -> Self {
+        self.dark = dark.into();
+        self.light = light.into();
+        self
+    }
+
+    // leaves out width and height so the code fills whatever box it's put in
+    pub fn scalable(mut self, enabled: bool) -> Self {
+        self.scalable = enabled;
+        self
+    }
+}
+
+impl Renderer for Svg {
+    type Output = String;
+
+    fn layout(&self) -> (usize, usize) {
+        (self.quiet_zone, self.module_size)
+    }
+
+    fn draw(&self, canvas: &Canvas) -> String {
+        let (width, height) = canvas.modules();
+        let mut svg = String::new();
+
+        write!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {width} {height}""#).unwrap();
+        if !self.scalable {
+            let (width, height) = canvas.size();
+            write!(svg, r#" width="{width}" height="{height}""#).unwrap();
+        }
+        write!(svg, r#" shape-rendering="crispEdges">"#).unwrap();
+        write!(svg, r#"<rect width="{width}" height="{height}" fill="{}"/>"#, Attr(&self.light)).unwrap();
+        write!(svg, r#"<path fill="{}" d=""#, Attr(&self.dark)).unwrap();
+
+        // one rectangle per horizontal run of dark modules keeps the path short
+        for y in 0..height {
+            let mut x = 0;
+            while x < width {
+                if !canvas.module(x, y) {
+                    x += 1;
+                    continue;
+                }
+
+                let start = x;
+                while x < width && canvas.module(x, y) { x += 1 };
+
+                write!(svg, "M{start},{y}h{}v1H{start}z", x - start).unwrap();
+            }
+        }
+
+        svg.push_str(r#""/></svg>"#);
+        svg
+    }
+}
+
+// the colors are written into attributes as given, so they can't be allowed to close the quotes or open tags
+struct Attr<'a>(&'a str);
+
+impl core::fmt::Display for Attr<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&#39;")?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_svg() {
+    use crate::{ErrorCorrectLv, Mode, QrMatrix, Version};
+
+    let mat = QrMatrix::generate(b"HELLO WORLD", Mode::Alphanumeric, Version::new(1).unwrap(), ErrorCorrectLv::Q).unwrap();
+    let svg = Svg::new().module_size(4).quiet_zone(2).colors("navy", "none").render(&mat);
+
+    assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 25 25" width="100" height="100""#));
+    assert!(svg.contains(r#"<rect width="25" height="25" fill="none"/><path fill="navy" d="M2,2h7v1H2z"#));
+    assert!(svg.ends_with(r#"z"/></svg>"#));
+
+    let runs = (0..21).map(|y| (0..21).filter(|x| mat.get(*x, y) && (*x == 0 || !mat.get(x - 1, y))).count()).sum::<usize>();
+    assert_eq!(svg.matches('M').count(), runs);
+
+    let svg = Svg::new().colors(r#"red"/><script>alert(1)</script>"#, "a&b").render(&mat);
+    assert!(svg.contains(r#"fill="a&amp;b"/><path fill="red&quot;/&gt;&lt;script&gt;alert(1)&lt;/script&gt;" d=""#));
+    assert!(!svg.contains("<script"));
+
+    assert!(Svg::new().scalable(true).render(&mat).starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 29 29" shape-rendering"#));
+}
@@ -0,0 +1,176 @@
+use alloc::string::String;
+use core::fmt::Write;
+
+use super::{Canvas, Renderer};
+
+// two full blocks per module in 256-color escapes, what Display prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ansi {
+    quiet_zone: usize,
+}
+
+impl Default for Ansi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ansi {
+    pub fn new() -> Self {
+        Self { quiet_zone: 4 }
+    }
+
+    pub fn quiet_zone(mut self, modules: usize) -> Self {
+        self.quiet_zone = modules;
+        self
+    }
+}
+
+impl Renderer for Ansi {
+    type Output = String;
+
+    fn layout(&self) -> (usize, usize) {
+        (self.quiet_zone, 1)
+    }
+
+    fn draw(&self, canvas: &Canvas) -> String {
+        let mut s = String::new();
+        let mut color = None;
+
+        let (width, height) = canvas.modules();
+
+        for y in 0..height {
+            for x in 0..width {
+                let c = if canvas.module(x, y) { 232 } else { 255 };
+                if color != Some(c) {
+                    write!(s, "\x1b[38;5;{c}m").unwrap();
+                    color = Some(c);
+                }
+
+                s.push_str("██");
+            }
+            s.push('\n');
+        }
+
+        s.push_str("\x1b[0m");
+        s
+    }
+}
+
+// one character per module column and two module rows, colored with foreground and background escapes
+// unless it's plain, where the glyphs draw the dark modules (or the light ones when inverted, for dark terminals)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HalfBlock {
+    quiet_zone: usize,
+    plain: bool,
+    inverted: bool,
+}
+
+impl Default for HalfBlock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HalfBlock {
+    pub fn new() -> Self {
+        Self {
+            quiet_zone: 4,
+            plain: false,
+            inverted: false,
+        }
+    }
+
+    pub fn quiet_zone(mut self, modules: usize) -> Self {
+        self.quiet_zone = modules;
+        self
+    }
+
+    pub fn plain(mut self, enabled: bool) -> Self {
+        self.plain = enabled;
+        self
+    }
+
+    pub fn inverted(mut self, enabled: bool) -> Self {
+        self.inverted = enabled;
+        self
+    }
+}
+
+impl Renderer for HalfBlock {
+    type Output = String;
+
+    fn layout(&self) -> (usize, usize) {
+        (self.quiet_zone, 1)
+    }
+
+    fn draw(&self, canvas: &Canvas) -> String {
+        let mut s = String::new();
+        let color = |dark| if dark { 232 } else { 255 };
+        let (width, height) = canvas.modules();
+
+        for y in (0..height).step_by(2) {
+            let mut last = None;
+
+            for x in 0..width {
+                // past the last row counts as light
+                let (top, bottom) = (canvas.module(x, y), canvas.module(x, y + 1));
+
+                if self.plain {
+                    s.push(match (top ^ self.inverted, bottom ^ self.inverted) {
+                        (false, false) => ' ',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (true, true) => '█',
+                    });
+                    continue;
+                }
+
+                let colors = (color(top), color(bottom));
+                if last != Some(colors) {
+                    write!(s, "\x1b[38;5;{};48;5;{}m", colors.0, colors.1).unwrap();
+                    last = Some(colors);
+                }
+
+                s.push('▀');
+            }
+
+            if !self.plain {
+                s.push_str("\x1b[0m");
+            }
+            s.push('\n');
+        }
+
+        s
+    }
+}
+
+#[test]
+fn test_text() {
+    use alloc::string::ToString;
+
+    use crate::{ErrorCorrectLv, Mode, QrMatrix, Version};
+
+    let mat = QrMatrix::generate(b"HELLO WORLD", Mode::Alphanumeric, Version::new(1).unwrap(), ErrorCorrectLv::Q).unwrap();
+
+    let ansi = mat.to_string();
+    assert_eq!(ansi, mat.render(&Ansi::new()));
+    assert_eq!(ansi.matches('\n').count(), 29);
+    assert!(ansi.lines().nth(4).unwrap().starts_with("████████\x1b[38;5;232m██████████████\x1b[38;5;255m██"));
+
+    let half = mat.render(&HalfBlock::new().quiet_zone(0));
+    assert_eq!(half.lines().count(), 11);
+    assert!(half.starts_with("\x1b[38;5;232;48;5;232m▀\x1b[38;5;232;48;5;255m▀▀▀▀▀\x1b[38;5;232;48;5;232m▀\x1b[38;5;255;48;5;255m▀"));
+    assert!(half.lines().last().unwrap().starts_with("\x1b[38;5;232;48;5;255m▀▀▀▀▀▀▀"));
+
+    let plain = mat.render(&HalfBlock::new().quiet_zone(1).plain(true));
+    assert_eq!(plain.lines().count(), 12);
+    assert!(plain.lines().all(|l| l.chars().count() == 23));
+    assert!(plain.starts_with(" ▄▄▄▄▄▄▄ "));
+    assert!(plain.lines().next().unwrap().ends_with("▄▄▄▄▄▄▄ "));
+    assert!(plain.lines().nth(1).unwrap().starts_with(" █ ▄▄▄ █ "));
+    assert!(!plain.contains('\x1b'));
+
+    let inverted = mat.render(&HalfBlock::new().quiet_zone(1).plain(true).inverted(true));
+    assert!(inverted.starts_with("█▀▀▀▀▀▀▀█"));
+}
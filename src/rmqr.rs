@@ -0,0 +1,364 @@
+use crate::matrix::{flip_mask, place_data, skip_filled, UnfinishedMatrix};
+use crate::{encode, structure, BlocksData, Error, ErrorCorrectLv, Mode, QrMatrix, Segment};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RmqrVersion(u8);
+
+impl RmqrVersion {
+    pub const MIN: Self = Self(0);
+    pub const MAX: Self = Self(31);
+
+    pub fn new(height: usize, width: usize) -> Result<Self, Error> {
+        RMQR_SIZES.iter()
+            .position(|s| *s == (height, width))
+            .map(|v| Self(v as u8))
+            .ok_or(Error::InvalidRmqrSize { height, width })
+    }
+
+    pub fn height(self) -> usize {
+        RMQR_SIZES[self.0 as usize].0
+    }
+
+    pub fn width(self) -> usize {
+        RMQR_SIZES[self.0 as usize].1
+    }
+
+    // fewest modules that fit, and the lower one of two with the same area
+    pub fn smallest_version_for(segments: &[Segment], ec: ErrorCorrectLv, max_height: usize) -> Result<Self, Error> {
+        let versions = (0..32).map(Self).filter(|v| v.height() <= max_height);
+
+        versions.clone()
+            .filter(|v| v.fits(segments, ec).is_ok())
+            .min_by_key(|v| (v.width() * v.height(), v.height()))
+            .ok_or_else(|| match versions.clone().next_back() {
+                Some(v) => v.fits(segments, ec).unwrap_err(),
+                None => Error::InvalidRmqrSize { height: max_height, width: 0 },
+            })
+    }
+
+    pub fn char_count_length(self, mode: Mode) -> usize {
+        RMQR_CHAR_COUNT_LENGTHS[self.0 as usize][mode as usize]
+    }
+
+    pub fn bit_length(self, segments: &[Segment]) -> usize {
+        segments.iter()
+            .map(|s| 3 + self.char_count_length(s.mode()) + s.mode().data_bit_length(s.char_count()))
+            .sum()
+    }
+
+    pub(crate) fn fits(self, segments: &[Segment], ec: ErrorCorrectLv) -> Result<(), Error> {
+        let capacity = self.max_data_bytes(ec)? * 8;
+        let needed = self.bit_length(segments);
+
+        if needed > capacity || segments.iter().any(|s| s.char_count() >= 1 << self.char_count_length(s.mode())) {
+            return Err(Error::DataTooLong { needed, capacity });
+        }
+        Ok(())
+    }
+
+    fn table_row(self, ec: ErrorCorrectLv) -> Result<&'static [usize; 6], Error> {
+        match ec {
+            ErrorCorrectLv::M => Ok(&RMQR_BYTES[self.0 as usize * 2]),
+            ErrorCorrectLv::H => Ok(&RMQR_BYTES[self.0 as usize * 2 + 1]),
+            _ => Err(Error::UnsupportedEcLevel(ec)),
+        }
+    }
+
+    pub(crate) fn max_data_bytes(self, ec: ErrorCorrectLv) -> Result<usize, Error> {
+        Ok(self.table_row(ec)?[0])
+    }
+
+    fn blocks_data(self, ec: ErrorCorrectLv) -> Result<BlocksData, Error> {
+        let d = self.table_row(ec)?;
+
+        Ok(BlocksData {
+            ec_bytes: d[1],
+            g1_blocks: d[2],
+            g1_bytes: d[3],
+            g2_blocks: d[4],
+            g2_bytes: d[5],
+        })
+    }
+}
+
+impl QrMatrix {
+    pub fn generate_rmqr(string: &[u8], mode: Mode, version: RmqrVersion, ec: ErrorCorrectLv) -> Result<Self, Error> {
+        Self::generate_rmqr_segments(&[Segment::new(mode, string)], version, ec)
+    }
+
+    pub fn generate_rmqr_segments(segments: &[Segment], version: RmqrVersion, ec: ErrorCorrectLv) -> Result<Self, Error> {
+        let data = encode::encode_rmqr(segments, version, ec)?;
+        let codewords = structure::interleave(&data, &version.blocks_data(ec)?);
+
+        let mut mat = function_patterns(version);
+
+        // the right edge is all function patterns, so the column pairs start one in
+        let mut cursor = (version.width() - 2, version.height() - 1, true, true);
+        skip_filled(&mat, &mut cursor);
+        for mut b in codewords {
+            for _ in 0..8 {
+                place_data(&mut mat, &mut cursor, b & 0x80 != 0);
+                b <<= 1;
+            }
+        }
+
+        // there's only the one mask, so nothing to choose
+        flip_mask(&mut mat.matrix, &mat.done, 4);
+        place_format(&mut mat.matrix, version, ec);
+        mat.matrix.mask = Some(4);
+        Ok(mat.matrix)
+    }
+}
+
+fn function_patterns(version: RmqrVersion) -> UnfinishedMatrix {
+    let (width, height) = (version.width(), version.height());
+    let mut mat = UnfinishedMatrix::new(width, height, None);
+
+    mat.set_outline_box(0, 0, 7, 7, true);
+    mat.set_outline_box(1, 1, 5, 5, false);
+    mat.set_filled_box(2, 2, 3, 3, true);
+
+    // the separator's bottom row is cut off by the edge at height 7
+    mat.set_vline(7, 0, height.min(8), false);
+    if height > 7 {
+        mat.set_hline(0, 7, 7, false);
+    }
+
+    // finder sub pattern
+    mat.set_outline_box(width - 5, height - 5, 5, 5, true);
+    mat.set_outline_box(width - 4, height - 4, 3, 3, false);
+    mat.set(width - 3, height - 3, true);
+
+    // corner finder patterns
+    mat.set_hline(width - 2, 0, 2, true);
+    mat.set(width - 2, 1, false);
+    mat.set(width - 1, 1, true);
+    mat.set_hline(0, height - 1, 3, true);
+    if height >= 11 {
+        mat.set(0, height - 2, true);
+        mat.set(1, height - 2, false);
+    }
+
+    let align = RMQR_ALIGN_LOCATIONS[RMQR_WIDTHS.iter().position(|w| *w == width).unwrap()];
+    for x in align {
+        mat.set_outline_box(x - 1, 0, 3, 3, true);
+        mat.set_outline_box(x - 1, height - 3, 3, 3, true);
+    }
+
+    // timing along all four edges and down through every alignment pattern, wherever nothing else is
+    for x in 0..width {
+        mat.set(x, 0, x & 1 == 0);
+        mat.set(x, height - 1, x & 1 == 0);
+    }
+    for x in [0, width - 1].iter().chain(align) {
+        for y in 0..height {
+            mat.set(*x, y, y & 1 == 0);
+        }
+    }
+
+    // reserved area
+    mat.set_filled_box(8, 1, 3, 5, false);
+    mat.set_vline(11, 1, 3, false);
+    mat.set_filled_box(width - 8, height - 6, 3, 5, false);
+    mat.set_hline(width - 5, height - 6, 3, false);
+
+    mat
+}
+
+fn place_format(mat: &mut QrMatrix, version: RmqrVersion, ec: ErrorCorrectLv) {
+    let format = format_info(version, ec);
+    let (width, height) = (mat.width(), mat.height());
+
+    // least significant bit first, down the columns of a 5 high block and then along the 3 left over
+    for i in 0..18 {
+        let (near, far) = if i < 15 {
+            ((8 + i / 5, 1 + i % 5), (width - 8 + i / 5, height - 6 + i % 5))
+        } else {
+            ((11, i - 14), (width - 20 + i, height - 6))
+        };
+
+        mat.set(near.0, near.1, (format ^ 0b011111101010110010) >> i & 1 != 0);
+        mat.set(far.0, far.1, (format ^ 0b100000101001111011) >> i & 1 != 0);
+    }
+}
+
+// the ec level and version, bch(18, 6) with the same generator as the full qr version info
+fn format_info(version: RmqrVersion, ec: ErrorCorrectLv) -> u32 {
+    let data = ((ec == ErrorCorrectLv::H) as u32) << 5 | version.0 as u32;
+
+    let mut rem = data << 12;
+    for i in (12..18).rev() {
+        if rem >> i & 1 != 0 {
+            rem ^= 0x1f25 << (i - 12);
+        }
+    }
+
+    data << 12 | rem
+}
+
+// R7x43 through R17x139, height then width
+static RMQR_SIZES: [(usize, usize); 32] = [
+    (7, 43), (7, 59), (7, 77), (7, 99), (7, 139),
+    (9, 43), (9, 59), (9, 77), (9, 99), (9, 139),
+    (11, 27), (11, 43), (11, 59), (11, 77), (11, 99), (11, 139),
+    (13, 27), (13, 43), (13, 59), (13, 77), (13, 99), (13, 139),
+    (15, 43), (15, 59), (15, 77), (15, 99), (15, 139),
+    (17, 43), (17, 59), (17, 77), (17, 99), (17, 139),
+];
+
+static RMQR_WIDTHS: [usize; 6] = [27, 43, 59, 77, 99, 139];
+
+static RMQR_ALIGN_LOCATIONS: [&[usize]; 6] = [
+    &[],
+    &[21],
+    &[19, 39],
+    &[25, 51],
+    &[23, 49, 75],
+    &[27, 55, 83, 111],
+];
+
+// numeric, alphanumeric, bytes, kanji
+static RMQR_CHAR_COUNT_LENGTHS: [[usize; 4]; 32] = [
+    [4, 3, 3, 2],
+    [5, 5, 4, 3],
+    [6, 5, 5, 4],
+    [7, 6, 5, 5],
+    [7, 6, 6, 5],
+    [5, 5, 4, 3],
+    [6, 5, 5, 4],
+    [7, 6, 5, 5],
+    [7, 6, 6, 5],
+    [8, 7, 6, 6],
+    [4, 4, 3, 2],
+    [6, 5, 5, 4],
+    [7, 6, 5, 5],
+    [7, 6, 6, 5],
+    [8, 7, 6, 6],
+    [8, 7, 7, 6],
+    [5, 5, 4, 3],
+    [6, 6, 5, 5],
+    [7, 6, 6, 5],
+    [7, 7, 6, 6],
+    [8, 7, 7, 6],
+    [8, 8, 7, 7],
+    [7, 6, 6, 5],
+    [7, 7, 6, 5],
+    [8, 7, 7, 6],
+    [8, 7, 7, 6],
+    [9, 8, 7, 7],
+    [7, 6, 6, 5],
+    [8, 7, 6, 6],
+    [8, 7, 7, 6],
+    [8, 8, 7, 6],
+    [9, 8, 8, 7],
+];
+
+//  Data bytes | EC bytes | #Blocks G1 | #Bytes in G1 blocks | #Blocks G2 | #Bytes in G2 blocks, M then H
+static RMQR_BYTES: [[usize; 6]; 32 * 2] = [
+    [6, 7, 1, 6, 0, 0],
+    [3, 10, 1, 3, 0, 0],
+    [12, 9, 1, 12, 0, 0],
+    [7, 14, 1, 7, 0, 0],
+    [20, 12, 1, 20, 0, 0],
+    [10, 22, 1, 10, 0, 0],
+    [28, 16, 1, 28, 0, 0],
+    [14, 30, 1, 14, 0, 0],
+    [44, 12, 2, 22, 0, 0],
+    [24, 22, 2, 12, 0, 0],
+    [12, 9, 1, 12, 0, 0],
+    [7, 14, 1, 7, 0, 0],
+    [21, 12, 1, 21, 0, 0],
+    [11, 22, 1, 11, 0, 0],
+    [31, 18, 1, 31, 0, 0],
+    [17, 16, 1, 8, 1, 9],
+    [42, 24, 1, 42, 0, 0],
+    [22, 22, 2, 11, 0, 0],
+    [63, 18, 1, 31, 1, 32],
+    [33, 22, 3, 11, 0, 0],
+    [7, 8, 1, 7, 0, 0],
+    [5, 10, 1, 5, 0, 0],
+    [19, 12, 1, 19, 0, 0],
+    [11, 20, 1, 11, 0, 0],
+    [31, 16, 1, 31, 0, 0],
+    [15, 16, 1, 7, 1, 8],
+    [43, 24, 1, 43, 0, 0],
+    [23, 22, 1, 11, 1, 12],
+    [57, 16, 1, 28, 1, 29],
+    [29, 30, 1, 14, 1, 15],
+    [84, 16, 3, 28, 0, 0],
+    [42, 30, 3, 14, 0, 0],
+    [12, 9, 1, 12, 0, 0],
+    [7, 14, 1, 7, 0, 0],
+    [27, 14, 1, 27, 0, 0],
+    [13, 28, 1, 13, 0, 0],
+    [38, 22, 1, 38, 0, 0],
+    [20, 20, 2, 10, 0, 0],
+    [53, 16, 1, 26, 1, 27],
+    [29, 28, 1, 14, 1, 15],
+    [73, 20, 1, 36, 1, 37],
+    [35, 26, 1, 11, 2, 12],
+    [106, 20, 2, 35, 1, 36],
+    [54, 28, 2, 13, 2, 14],
+    [33, 18, 1, 33, 0, 0],
+    [15, 18, 1, 7, 1, 8],
+    [48, 26, 1, 48, 0, 0],
+    [26, 24, 2, 13, 0, 0],
+    [67, 18, 1, 33, 1, 34],
+    [31, 24, 2, 10, 1, 11],
+    [88, 24, 2, 44, 0, 0],
+    [48, 22, 4, 12, 0, 0],
+    [127, 24, 2, 42, 1, 43],
+    [69, 26, 1, 13, 4, 14],
+    [39, 22, 1, 39, 0, 0],
+    [21, 20, 1, 10, 1, 11],
+    [56, 16, 2, 28, 0, 0],
+    [28, 30, 2, 14, 0, 0],
+    [78, 22, 2, 39, 0, 0],
+    [38, 28, 1, 12, 2, 13],
+    [100, 30, 2, 50, 0, 0],
+    [56, 26, 4, 14, 0, 0],
+    [152, 20, 4, 38, 0, 0],
+    [76, 26, 2, 12, 4, 13],
+];
+
+#[test]
+fn test_rmqr() {
+    // every module left over after the function patterns holds a codeword bit, short of the remainder bits
+    for v in 0..32 {
+        let version = RmqrVersion(v);
+        let mat = function_patterns(version);
+        let free = (0..version.width() * version.height()).filter(|i| !mat.done.get(i % version.width(), i / version.width())).count();
+
+        assert_eq!(free / 8, version.blocks_data(ErrorCorrectLv::M).unwrap().codewords(), "{version:?}");
+        assert_eq!(free / 8, version.blocks_data(ErrorCorrectLv::H).unwrap().codewords(), "{version:?}");
+    }
+
+    let version = RmqrVersion::new(7, 43).unwrap();
+    let mat = QrMatrix::generate_rmqr(b"12345", Mode::Numeric, version, ErrorCorrectLv::M).unwrap();
+    assert_eq!((mat.width(), mat.height(), mat.mask()), (43, 7, Some(4)));
+    assert!((0..5).all(|i| mat.get(38 + i, 2) && mat.get(38 + i, 6) && mat.get(38, 2 + i) && mat.get(42, 2 + i)));
+    assert!(mat.get(40, 4) && !mat.get(40, 3) && !mat.get(21, 1) && mat.get(20, 1));
+
+    // both copies carry the same format info under their own masks
+    let read = |positions: &dyn Fn(usize) -> (usize, usize), mask: u32| {
+        (0..18).fold(0, |f, i| f | (mat.get(positions(i).0, positions(i).1) as u32) << i) ^ mask
+    };
+    let near = read(&|i| if i < 15 { (8 + i / 5, 1 + i % 5) } else { (11, i - 14) }, 0b011111101010110010);
+    let far = read(&|i| if i < 15 { (35 + i / 5, 1 + i % 5) } else { (23 + i, 1) }, 0b100000101001111011);
+    assert_eq!((near, far), (0, 0));
+    assert_eq!(format_info(RmqrVersion::MAX, ErrorCorrectLv::H), 0b111111_101110000101);
+
+    let pbm = mat.render(&crate::Pbm::new().quiet_zone(2));
+    assert_eq!(&pbm[..10], b"P4\n47 11\n\0");
+
+    assert_eq!(RmqrVersion::new(8, 43), Err(Error::InvalidRmqrSize { height: 8, width: 43 }));
+    assert_eq!(QrMatrix::generate_rmqr(b"1", Mode::Numeric, version, ErrorCorrectLv::L), Err(Error::UnsupportedEcLevel(ErrorCorrectLv::L)));
+
+    let segments = [Segment::new(Mode::Alphanumeric, b"CABLE-42")];
+    assert_eq!(RmqrVersion::smallest_version_for(&segments, ErrorCorrectLv::M, 17), RmqrVersion::new(11, 27));
+    assert_eq!(RmqrVersion::smallest_version_for(&segments, ErrorCorrectLv::M, 7), RmqrVersion::new(7, 59));
+    assert_eq!(
+        RmqrVersion::smallest_version_for(&[Segment::new(Mode::Bytes, &[0; 50])], ErrorCorrectLv::H, 7),
+        Err(Error::DataTooLong { needed: 3 + 6 + 400, capacity: 24 * 8 }),
+    );
+}
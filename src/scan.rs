@@ -0,0 +1,438 @@
+use crate::{Decoded, Error, QrMatrix};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GrayImage<'a> {
+    width: usize,
+    height: usize,
+    pixels: &'a [u8],
+}
+
+impl<'a> GrayImage<'a> {
+    pub fn new(width: usize, height: usize, pixels: &'a [u8]) -> Result<Self, Error> {
+        if pixels.len() != width * height {
+            return Err(Error::ImageSizeMismatch { expected: width * height, actual: pixels.len() });
+        }
+
+        Ok(Self { width, height, pixels })
+    }
+
+    pub fn width(&self) -> usize { self.width }
+
+    pub fn height(&self) -> usize { self.height }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[x + y * self.width]
+    }
+}
+
+pub fn scan(image: &GrayImage) -> Vec<Decoded> {
+    let bits = binarize(image);
+
+    // a single row through a finder can be a coincidence, one seen from several rows isn't
+    let mut finders = find_finders(&bits);
+    finders.sort_by_key(|f| core::cmp::Reverse(f.hits));
+    if finders.iter().filter(|f| f.hits >= 2).count() >= 3 {
+        finders.retain(|f| f.hits >= 2);
+    }
+    finders.truncate(30);
+
+    let mut used = vec![false; finders.len()];
+    let mut result = Vec::new();
+
+    for i in 0..finders.len() {
+        for j in i + 1..finders.len() {
+            for k in j + 1..finders.len() {
+                if used[i] || used[j] || used[k] { continue };
+
+                if let Some(decoded) = decode_finders(&bits, [&finders[i], &finders[j], &finders[k]]) {
+                    used[i] = true;
+                    used[j] = true;
+                    used[k] = true;
+                    result.push(decoded);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+struct Bitmap {
+    width: usize,
+    height: usize,
+    dark: Vec<bool>,
+}
+
+impl Bitmap {
+    fn get(&self, x: isize, y: isize) -> Option<bool> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height { return None };
+        Some(self.dark[x as usize + y as usize * self.width])
+    }
+
+    // (start, length, dark) of every run in a row between x0 and x1
+    fn row_runs(&self, y: usize, x0: usize, x1: usize) -> Vec<(usize, usize, bool)> {
+        let mut runs: Vec<(usize, usize, bool)> = Vec::new();
+
+        for x in x0..x1 {
+            let dark = self.dark[x + y * self.width];
+            match runs.last_mut() {
+                Some(run) if run.2 == dark => run.1 += 1,
+                _ => runs.push((x, 1, dark)),
+            }
+        }
+
+        runs
+    }
+}
+
+const BLOCK: usize = 8;
+
+// thresholds every 8x8 block against the 5x5 blocks around it, so uneven lighting across a photo doesn't matter
+fn binarize(image: &GrayImage) -> Bitmap {
+    let bw = image.width.div_ceil(BLOCK);
+    let bh = image.height.div_ceil(BLOCK);
+    let mut black_points = vec![0; bw * bh];
+
+    for by in 0..bh {
+        for bx in 0..bw {
+            let (mut sum, mut min, mut max, mut count) = (0, 255, 0, 0);
+            for y in by * BLOCK..((by + 1) * BLOCK).min(image.height) {
+                for x in bx * BLOCK..((bx + 1) * BLOCK).min(image.width) {
+                    let p = image.get(x, y) as usize;
+                    sum += p;
+                    min = min.min(p);
+                    max = max.max(p);
+                    count += 1;
+                }
+            }
+
+            // a flat block is either background or the inside of something dark, so lean on the neighbours
+            let mut average = sum / count;
+            if max - min <= 24 {
+                average = min / 2;
+
+                if by > 0 && bx > 0 {
+                    let neighbours = (
+                        black_points[bx + (by - 1) * bw]
+                        + 2 * black_points[bx - 1 + by * bw]
+                        + black_points[bx - 1 + (by - 1) * bw]
+                    ) / 4;
+
+                    if min < neighbours {
+                        average = neighbours;
+                    }
+                }
+            }
+
+            black_points[bx + by * bw] = average;
+        }
+    }
+
+    let mut dark = vec![false; image.width * image.height];
+    for by in 0..bh {
+        for bx in 0..bw {
+            let (mut sum, mut count) = (0, 0);
+            for y in by.saturating_sub(2)..(by + 3).min(bh) {
+                for x in bx.saturating_sub(2)..(bx + 3).min(bw) {
+                    sum += black_points[x + y * bw];
+                    count += 1;
+                }
+            }
+
+            let threshold = sum / count;
+            for y in by * BLOCK..((by + 1) * BLOCK).min(image.height) {
+                for x in bx * BLOCK..((bx + 1) * BLOCK).min(image.width) {
+                    dark[x + y * image.width] = image.get(x, y) as usize <= threshold;
+                }
+            }
+        }
+    }
+
+    Bitmap { width: image.width, height: image.height, dark }
+}
+
+// relative run widths across the centre, a zero means the run isn't checked
+const FINDER: [f64; 5] = [1.0, 1.0, 3.0, 1.0, 1.0];
+const ALIGNMENT: [f64; 5] = [0.0, 1.0, 1.0, 1.0, 0.0];
+
+fn module_size(runs: [usize; 5], weights: [f64; 5]) -> Option<f64> {
+    let total = runs.iter().zip(weights).filter(|(_, w)| *w > 0.0).map(|(r, _)| *r as f64).sum::<f64>();
+    let module = total / weights.iter().sum::<f64>();
+
+    (module >= 1.0 && runs.iter().zip(weights).all(|(r, w)| w == 0.0 || (*r as f64 - w * module).abs() < w * module / 2.0))
+        .then_some(module)
+}
+
+// the five runs of a pattern crossing (x, y) along (dx, dy), and where the centre run's middle lies on that axis
+fn cross_runs(bits: &Bitmap, x: f64, y: f64, (dx, dy): (isize, isize), max: usize) -> Option<([usize; 5], f64)> {
+    let (x, y) = (x as isize, y as isize);
+    if bits.get(x, y) != Some(true) { return None };
+
+    let mut runs = [0; 5];
+    let mut back = 0;
+
+    for (dir, side) in [(-1, [2, 1, 0]), (1, [2, 3, 4])] {
+        let mut i = 0;
+        let mut step = if dir < 0 { 0 } else { 1 };
+
+        while let Some(dark) = bits.get(x + dx * dir * step, y + dy * dir * step) {
+            if dark != (i != 1) {
+                i += 1;
+                if i == 3 { break };
+            }
+
+            // the outer runs can merge into whatever is around the pattern, so they're only cut off
+            if runs[side[i]] >= max {
+                if i < 2 { return None };
+                break;
+            }
+
+            runs[side[i]] += 1;
+            step += 1;
+        }
+
+        if runs[side[2]] == 0 { return None };
+        if dir < 0 { back = runs[2] };
+    }
+
+    let start = if dx != 0 { x } else { y };
+    Some((runs, (start - back as isize + 1) as f64 + runs[2] as f64 / 2.0))
+}
+
+// a row hit confirmed across the column and then the row again, as (x, y, module size)
+fn cross_check(bits: &Bitmap, x: f64, y: f64, weights: [f64; 5], max: usize) -> Option<(f64, f64, f64)> {
+    let (v, y) = cross_runs(bits, x, y, (0, 1), max)?;
+    let v = module_size(v, weights)?;
+    let (h, x) = cross_runs(bits, x, y, (1, 0), max)?;
+    let h = module_size(h, weights)?;
+
+    Some((x, y, (v + h) / 2.0))
+}
+
+struct Pattern {
+    x: f64,
+    y: f64,
+    module: f64,
+    hits: usize,
+}
+
+fn find_finders(bits: &Bitmap) -> Vec<Pattern> {
+    let mut finders: Vec<Pattern> = Vec::new();
+
+    for y in 0..bits.height {
+        let runs = bits.row_runs(y, 0, bits.width);
+
+        for w in runs.windows(5).filter(|w| w[0].2) {
+            let Some(module) = module_size([w[0].1, w[1].1, w[2].1, w[3].1, w[4].1], FINDER) else { continue };
+
+            let x = w[2].0 as f64 + w[2].1 as f64 / 2.0;
+            let Some((x, y, module)) = cross_check(bits, x, y as f64 + 0.5, FINDER, (module * 5.0) as usize) else { continue };
+
+            add_hit(&mut finders, x, y, module);
+        }
+    }
+
+    finders
+}
+
+// rows through the same pattern get averaged into one candidate
+fn add_hit(candidates: &mut Vec<Pattern>, x: f64, y: f64, module: f64) {
+    let same = candidates.iter_mut().find(|f| {
+        (f.x - x).abs() <= f.module * 2.0
+            && (f.y - y).abs() <= f.module * 2.0
+            && (0.5..2.0).contains(&(module / f.module))
+    });
+
+    match same {
+        Some(f) => {
+            let hits = f.hits as f64;
+            f.x = (f.x * hits + x) / (hits + 1.0);
+            f.y = (f.y * hits + y) / (hits + 1.0);
+            f.module = (f.module * hits + module) / (hits + 1.0);
+            f.hits += 1;
+        },
+        None => candidates.push(Pattern { x, y, module, hits: 1 }),
+    }
+}
+
+fn find_alignment(bits: &Bitmap, (ex, ey): (f64, f64), module: f64) -> Option<(f64, f64)> {
+    let max = (module * 3.0) as usize + 1;
+
+    for radius in [4.0, 8.0, 16.0] {
+        let r = radius * module;
+        let x0 = (ex - r).max(0.0) as usize;
+        let x1 = ((ex + r) as usize).min(bits.width);
+        let y0 = (ey - r).max(0.0) as usize;
+        let y1 = ((ey + r) as usize).min(bits.height);
+
+        let mut candidates = Vec::new();
+
+        for y in y0..y1 {
+            let runs = bits.row_runs(y, x0, x1);
+
+            for w in runs.windows(5).filter(|w| w[0].2) {
+                let Some(m) = module_size([w[0].1, w[1].1, w[2].1, w[3].1, w[4].1], ALIGNMENT) else { continue };
+                if !(0.5..2.0).contains(&(m / module)) { continue };
+
+                let x = w[2].0 as f64 + w[2].1 as f64 / 2.0;
+                let Some((x, y, m)) = cross_check(bits, x, y as f64 + 0.5, ALIGNMENT, max) else { continue };
+
+                add_hit(&mut candidates, x, y, m);
+            }
+        }
+
+        // data modules line up like this by chance too, but rarely over several rows
+        let best = candidates.iter().min_by(|a, b| {
+            b.hits.cmp(&a.hits).then((a.x - ex).hypot(a.y - ey).total_cmp(&(b.x - ex).hypot(b.y - ey)))
+        });
+
+        if let Some(best) = best { return Some((best.x, best.y)) };
+    }
+
+    None
+}
+
+fn decode_finders(bits: &Bitmap, finders: [&Pattern; 3]) -> Option<Decoded> {
+    let [a, b, c] = finders;
+    let modules = [a.module, b.module, c.module];
+    if modules.iter().copied().fold(0.0, f64::max) > modules.iter().copied().fold(f64::MAX, f64::min) * 1.5 { return None };
+
+    let dist = |p: &Pattern, q: &Pattern| (p.x - q.x).hypot(p.y - q.y);
+
+    // the top left finder is the one across from the diagonal
+    let (tl, p, q) = if dist(b, c) >= dist(a, b) && dist(b, c) >= dist(a, c) {
+        (a, b, c)
+    } else if dist(a, c) >= dist(a, b) {
+        (b, a, c)
+    } else {
+        (c, a, b)
+    };
+
+    let (sp, sq) = (dist(tl, p), dist(tl, q));
+    let cos = ((p.x - tl.x) * (q.x - tl.x) + (p.y - tl.y) * (q.y - tl.y)) / (sp * sq);
+    if sp.max(sq) > sp.min(sq) * 2.0 || cos.abs() > 0.5 { return None };
+
+    // going clockwise from top left in image coordinates
+    let (tr, bl) = if (p.x - tl.x) * (q.y - tl.y) - (p.y - tl.y) * (q.x - tl.x) > 0.0 { (p, q) } else { (q, p) };
+
+    let modules_across = (dist(tl, tr) / ((tl.module + tr.module) / 2.0) + dist(tl, bl) / ((tl.module + bl.module) / 2.0)) / 2.0;
+    let version = ((modules_across + 7.0 - 17.0) / 4.0).round() as isize;
+    let module = (tl.module + tr.module + bl.module) / 3.0;
+
+    // the estimate drifts by a version or so on bigger symbols
+    [version, version - 1, version + 1].into_iter()
+        .filter(|v| (1..=40).contains(v))
+        .find_map(|v| sample(bits, [tl, tr, bl], v as usize * 4 + 17, module))
+}
+
+fn sample(bits: &Bitmap, [tl, tr, bl]: [&Pattern; 3], size: usize, module: f64) -> Option<Decoded> {
+    let far = size as f64 - 3.5;
+    let mut from = [(3.5, 3.5), (far, 3.5), (3.5, far), (far, far)];
+    let mut to = [(tl.x, tl.y), (tr.x, tr.y), (bl.x, bl.y), (tr.x + bl.x - tl.x, tr.y + bl.y - tl.y)];
+
+    // without perspective the bottom right alignment pattern sits on the parallelogram, so look for it nearby
+    if size > 21 {
+        let c = 1.0 - 3.0 / (size as f64 - 7.0);
+        let estimate = (tl.x + (tr.x + bl.x - 2.0 * tl.x) * c, tl.y + (tr.y + bl.y - 2.0 * tl.y) * c);
+
+        if let Some(p) = find_alignment(bits, estimate, module) {
+            from[3] = (size as f64 - 6.5, size as f64 - 6.5);
+            to[3] = p;
+        }
+    }
+
+    let transform = Perspective::new(from, to)?;
+    let mut mat = QrMatrix::new(size, size);
+    let mut erasures = Vec::new();
+
+    for y in 0..size {
+        for x in 0..size {
+            let (px, py) = transform.map(x as f64 + 0.5, y as f64 + 0.5);
+
+            match bits.get(px.floor() as isize, py.floor() as isize) {
+                Some(dark) => mat.set(x, y, dark),
+                None => erasures.push((x, y)),
+            }
+        }
+    }
+
+    mat.decode_with_erasures(&erasures).ok()
+}
+
+struct Perspective([f64; 8]);
+
+impl Perspective {
+    // maps each of the four `from` points onto the matching `to` point
+    fn new(from: [(f64, f64); 4], to: [(f64, f64); 4]) -> Option<Self> {
+        let mut m = [[0.0; 9]; 8];
+        for (i, ((x, y), (u, v))) in from.into_iter().zip(to).enumerate() {
+            m[i * 2] = [x, y, 1.0, 0.0, 0.0, 0.0, -x * u, -y * u, u];
+            m[i * 2 + 1] = [0.0, 0.0, 0.0, x, y, 1.0, -x * v, -y * v, v];
+        }
+
+        for c in 0..8 {
+            let pivot = (c..8).max_by(|a, b| m[*a][c].abs().total_cmp(&m[*b][c].abs()))?;
+            if m[pivot][c].abs() < 1e-9 { return None };
+            m.swap(c, pivot);
+
+            for r in 0..8 {
+                if r == c { continue };
+
+                let f = m[r][c] / m[c][c];
+                let pivot_row = m[c];
+                for (a, b) in m[r].iter_mut().zip(pivot_row).skip(c) {
+                    *a -= f * b;
+                }
+            }
+        }
+
+        Some(Self(core::array::from_fn(|i| m[i][8] / m[i][i])))
+    }
+
+    fn map(&self, x: f64, y: f64) -> (f64, f64) {
+        let h = &self.0;
+        let w = h[6] * x + h[7] * y + 1.0;
+
+        ((h[0] * x + h[1] * y + h[2]) / w, (h[3] * x + h[4] * y + h[5]) / w)
+    }
+}
+
+#[test]
+fn test_scan() {
+    use crate::{ErrorCorrectLv, Mode, Version};
+
+    let a = QrMatrix::generate(b"HELLO WORLD", Mode::Alphanumeric, Version::new(1).unwrap(), ErrorCorrectLv::Q).unwrap();
+    let b = QrMatrix::generate(b"https://example.com/labels/0042", Mode::Bytes, Version::new(8).unwrap(), ErrorCorrectLv::M).unwrap();
+
+    // one code straight, the other tilted and seen at an angle, with a lighting gradient and some noise
+    let (width, height) = (640, 400);
+    let placed = [
+        (&a, Perspective::new([(30.0, 60.0), (198.0, 60.0), (30.0, 228.0), (198.0, 228.0)], [(0.0, 0.0), (21.0, 0.0), (0.0, 21.0), (21.0, 21.0)]).unwrap()),
+        (&b, Perspective::new([(300.0, 40.0), (610.0, 75.0), (280.0, 370.0), (590.0, 340.0)], [(0.0, 0.0), (49.0, 0.0), (0.0, 49.0), (49.0, 49.0)]).unwrap()),
+    ];
+
+    let mut seed = 0x2545f491_u32;
+    let mut pixels = vec![0; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let dark = placed.iter().any(|(mat, t)| {
+                let (mx, my) = t.map(x as f64 + 0.5, y as f64 + 0.5);
+                mx >= 0.0 && my >= 0.0 && (mx as usize) < mat.size() && (my as usize) < mat.size() && mat.get(mx as usize, my as usize)
+            });
+
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+
+            let light = 130 + x * 90 / width;
+            pixels[x + y * width] = (if dark { 30 } else { light } + (seed % 30) as usize) as u8;
+        }
+    }
+
+    let image = GrayImage::new(width, height, &pixels).unwrap();
+    let mut found = scan(&image).iter().map(|d| d.data()).collect::<Vec<_>>();
+    found.sort();
+
+    assert_eq!(found, [b"HELLO WORLD".to_vec(), b"https://example.com/labels/0042".to_vec()]);
+    assert_eq!(GrayImage::new(2, 2, &[0; 3]), Err(Error::ImageSizeMismatch { expected: 4, actual: 3 }));
+}
@@ -0,0 +1,183 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{encode, Error, ErrorCorrectLv, Mode, QrBuilder, Version};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment<'a> {
+    mode: Mode,
+    data: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    pub const fn new(mode: Mode, data: &'a [u8]) -> Self {
+        Self { mode, data }
+    }
+
+    pub const fn mode(&self) -> Mode { self.mode }
+
+    pub const fn data(&self) -> &'a [u8] { self.data }
+
+    pub const fn char_count(&self) -> usize {
+        self.mode.char_count(self.data.len())
+    }
+
+    pub const fn bit_length(&self, version: Version) -> usize {
+        4 + version.char_count_length(self.mode) + self.mode.data_bit_length(self.char_count())
+    }
+
+    pub fn total_bit_length(segments: &[Self], version: Version) -> usize {
+        segments.iter().map(|s| s.bit_length(version)).sum()
+    }
+
+    // kanji is left out, utf-8 text can pass for shift jis byte for byte
+    pub fn optimize(string: &'a [u8], version: Version) -> Vec<Self> {
+        Self::optimize_modes(string, version, false)
+    }
+
+    // for input that's already shift jis, kanji runs are considered too
+    pub fn optimize_sjis(string: &'a [u8], version: Version) -> Vec<Self> {
+        Self::optimize_modes(string, version, true)
+    }
+
+    fn optimize_modes(string: &'a [u8], version: Version, sjis: bool) -> Vec<Self> {
+        if string.is_empty() { return Vec::new() };
+
+        // the cost of a run only depends on how many characters are in its last group, so every
+        // (mode, chars in group) pair is a state and the exact minimum falls out of a shortest
+        // path over the input positions
+        let mut cost = vec![[usize::MAX; STATES]; string.len() + 1];
+        let mut from = vec![[(0, 0, false); STATES]; string.len() + 1];
+
+        for i in 0..string.len() {
+            let (best_state, best) = if i == 0 {
+                (0, 0)
+            } else {
+                cost[i].iter().copied().enumerate().min_by_key(|(_, c)| *c).unwrap()
+            };
+
+            for s in 0..STATES {
+                let (mode, group) = STATE_MODES[s];
+                let Some(len) = char_len(string, i, mode, sjis) else { continue };
+
+                // continue the current run
+                if cost[i][s] != usize::MAX {
+                    let next = state(mode, group + 1);
+                    let c = cost[i][s] + char_bits(mode, group);
+
+                    if c < cost[i + len][next] {
+                        cost[i + len][next] = c;
+                        from[i + len][next] = (i, s, false);
+                    }
+                }
+
+                // start a new run in this mode
+                if group == 0 && best != usize::MAX {
+                    let next = state(mode, 1);
+                    let c = best + 4 + version.char_count_length(mode) + char_bits(mode, 0);
+
+                    if c < cost[i + len][next] {
+                        cost[i + len][next] = c;
+                        from[i + len][next] = (i, best_state, true);
+                    }
+                }
+            }
+        }
+
+        let mut state = cost[string.len()].iter().enumerate().min_by_key(|(_, c)| **c).unwrap().0;
+        let mut segments = Vec::new();
+        let mut end = string.len();
+        let mut i = string.len();
+        loop {
+            let (prev, prev_state, new_run) = from[i][state];
+
+            if new_run {
+                segments.push(Self::new(STATE_MODES[state].0, &string[prev..end]));
+                end = prev;
+            }
+            if prev == 0 { break };
+
+            state = prev_state;
+            i = prev;
+        }
+
+        segments.reverse();
+        segments
+    }
+
+    pub fn optimize_smallest(string: &'a [u8], ec: ErrorCorrectLv) -> Result<(Version, Vec<Self>), Error> {
+        let version = QrBuilder::new(ec).symbol_version(None, string)?;
+        Ok((version, Self::optimize(string, version)))
+    }
+}
+
+const STATES: usize = 7;
+
+static STATE_MODES: [(Mode, usize); STATES] = [
+    (Mode::Numeric, 0),
+    (Mode::Numeric, 1),
+    (Mode::Numeric, 2),
+    (Mode::Alphanumeric, 0),
+    (Mode::Alphanumeric, 1),
+    (Mode::Bytes, 0),
+    (Mode::Kanji, 0),
+];
+
+fn state(mode: Mode, group: usize) -> usize {
+    match mode {
+        Mode::Numeric => group % 3,
+        Mode::Alphanumeric => 3 + group % 2,
+        Mode::Bytes => 5,
+        Mode::Kanji => 6,
+    }
+}
+
+fn char_bits(mode: Mode, group: usize) -> usize {
+    match (mode, group) {
+        (Mode::Numeric, 0) => 4,
+        (Mode::Numeric, _) => 3,
+        (Mode::Alphanumeric, 0) => 6,
+        (Mode::Alphanumeric, _) => 5,
+        (Mode::Bytes, _) => 8,
+        (Mode::Kanji, _) => 13,
+    }
+}
+
+fn char_len(string: &[u8], i: usize, mode: Mode, sjis: bool) -> Option<usize> {
+    let c = string[i];
+
+    match mode {
+        Mode::Numeric => c.is_ascii_digit().then_some(1),
+        Mode::Alphanumeric => encode::alphanumeric_table::get(c).map(|_| 1),
+        Mode::Bytes => Some(1),
+        Mode::Kanji if sjis => encode::kanji_value(c, *string.get(i + 1)?).map(|_| 2),
+        Mode::Kanji => None,
+    }
+}
+
+#[test]
+fn test_optimize() {
+    let v1 = Version::new(1).unwrap();
+    let s = b"ORDER 12345678901234 caf\xc3\xa9";
+    let segments = Segment::optimize(s, v1);
+
+    assert_eq!(&segments, &[
+        Segment::new(Mode::Alphanumeric, b"ORDER "),
+        Segment::new(Mode::Numeric, b"12345678901234"),
+        Segment::new(Mode::Bytes, b" caf\xc3\xa9"),
+    ]);
+    assert_eq!(Segment::total_bit_length(&segments, v1), 167);
+    assert!(Segment::total_bit_length(&segments, v1) < Segment::new(Mode::Bytes, s).bit_length(v1));
+
+    assert_eq!(Segment::optimize(b"0123456789", v1), &[Segment::new(Mode::Numeric, b"0123456789")]);
+    let s = b"a\x93\x5f\x93\x5f\x93\x5f\x93\x5f\x93\x5f";
+    assert_eq!(Segment::optimize_sjis(s, v1), &[
+        Segment::new(Mode::Bytes, &s[..1]),
+        Segment::new(Mode::Kanji, &s[1..]),
+    ]);
+    assert_eq!(Segment::optimize(s, v1), &[Segment::new(Mode::Bytes, s)]);
+
+    // utf-8 japanese is all valid shift jis kanji pairs, but mustn't be encoded as kanji
+    let s = "あいうえおかきくけこ".as_bytes();
+    assert_eq!(Segment::optimize(s, v1), &[Segment::new(Mode::Bytes, s)]);
+    assert!(Segment::optimize(b"", v1).is_empty());
+}
@@ -1,48 +1,83 @@
-use crate::{err_corr::generate_ec, ErrorCorrectLv, Version};
+use alloc::{vec, vec::Vec};
+
+use crate::err_corr::{generate_ec, MAX_EC_BYTES};
+use crate::{BlocksData, ErrorCorrectLv, Version};
 
 pub fn structure(data: &[u8], version: Version, ec: ErrorCorrectLv) -> Vec<u8> {
-    let blocks_data = version.blocks_data(ec);
-    let g2_off = blocks_data.g1_blocks * blocks_data.g1_bytes;
+    interleave(data, &version.blocks_data(ec))
+}
 
-    let mut result = Vec::with_capacity(data.len() + blocks_data.ec_bytes * (blocks_data.g1_blocks + blocks_data.g2_blocks));
+pub(crate) fn interleave(data: &[u8], blocks_data: &BlocksData) -> Vec<u8> {
+    let mut result = vec![0; blocks_data.codewords()];
+    interleave_into(data, blocks_data, &mut result);
+    result
+}
 
-    let g1 = (0..blocks_data.g1_blocks)
-        .map(|i| &data[i * blocks_data.g1_bytes..(i + 1) * blocks_data.g1_bytes])
-        .collect::<Vec<_>>();
-    let g2 = (0..blocks_data.g2_blocks)
-        .map(|i| &data[i * blocks_data.g2_bytes + g2_off..(i + 1) * blocks_data.g2_bytes + g2_off])
-        .collect::<Vec<_>>();
+// result has to hold all the codewords, data first and then ec, each a byte from every block in turn
+pub(crate) const fn interleave_into(data: &[u8], blocks_data: &BlocksData, result: &mut [u8]) {
+    let blocks = blocks_data.g1_blocks + blocks_data.g2_blocks;
+    let longest = if blocks_data.g1_bytes > blocks_data.g2_bytes { blocks_data.g1_bytes } else { blocks_data.g2_bytes };
+    let data_bytes = blocks_data.g1_blocks * blocks_data.g1_bytes + blocks_data.g2_blocks * blocks_data.g2_bytes;
 
-    for i in 0..blocks_data.g1_bytes.max(blocks_data.g2_bytes) {
-        if i < blocks_data.g1_bytes {
-            for b in g1.iter() {
-                result.push(b[i]);
+    let mut i = 0;
+    let mut j = 0;
+    while j < longest {
+        let mut offset = 0;
+        let mut b = 0;
+        while b < blocks {
+            let len = block_bytes(blocks_data, b);
+            if j < len {
+                result[i] = data[offset + j];
+                i += 1;
             }
+            offset += len;
+            b += 1;
         }
-        if i < blocks_data.g2_bytes {
-            for b in g2.iter() {
-                result.push(b[i]);
-            }
+        j += 1;
+    }
+
+    let mut ec = [0; MAX_EC_BYTES];
+    let (ec, _) = ec.split_at_mut(blocks_data.ec_bytes);
+    let mut offset = 0;
+    let mut b = 0;
+    while b < blocks {
+        let (block, _) = data.split_at(offset).1.split_at(block_bytes(blocks_data, b));
+        generate_ec(block, ec);
+
+        let mut j = 0;
+        while j < ec.len() {
+            result[data_bytes + j * blocks + b] = ec[j];
+            j += 1;
         }
+        offset += block.len();
+        b += 1;
     }
+}
+
+const fn block_bytes(blocks_data: &BlocksData, b: usize) -> usize {
+    if b < blocks_data.g1_blocks { blocks_data.g1_bytes } else { blocks_data.g2_bytes }
+}
 
-    let ec1 = g1.iter().map(|i| {
-        let mut i = i.to_vec();
-        i.reverse();
-        generate_ec(&i, blocks_data.ec_bytes)
-    }).collect::<Vec<_>>();
-    let ec2 = g2.iter().map(|i| {
-        let mut i = i.to_vec();
-        i.reverse();
-        generate_ec(&i, blocks_data.ec_bytes)
-    }).collect::<Vec<_>>();
+// splits the interleaved codewords back into blocks, each one data followed by ec
+pub fn deinterleave(codewords: &[u8], version: Version, ec: ErrorCorrectLv) -> Vec<Vec<u8>> {
+    let blocks_data = version.blocks_data(ec);
 
-    for i in (0..blocks_data.ec_bytes).rev() {
-        for b in ec1.iter() {
-            result.push(b[i]);
+    let mut result = (0..blocks_data.g1_blocks + blocks_data.g2_blocks)
+        .map(|b| Vec::with_capacity(block_bytes(&blocks_data, b) + blocks_data.ec_bytes))
+        .collect::<Vec<_>>();
+    let mut codewords = codewords.iter().copied();
+
+    for i in 0..blocks_data.g1_bytes.max(blocks_data.g2_bytes) {
+        for (b, block) in result.iter_mut().enumerate() {
+            if i < block_bytes(&blocks_data, b) {
+                block.extend(codewords.next());
+            }
         }
-        for b in ec2.iter() {
-            result.push(b[i]);
+    }
+
+    for _ in 0..blocks_data.ec_bytes {
+        for block in result.iter_mut() {
+            block.extend(codewords.next());
         }
     }
 
@@ -114,7 +149,7 @@ fn test() {
         0b11101100,
         0b00010001,
         0b11101100,
-    ], Version::new(5), ErrorCorrectLv::Q);
+    ], Version::new(5).unwrap(), ErrorCorrectLv::Q);
     assert_eq!(&m, &[
         0b01000011,
         0b11110110,